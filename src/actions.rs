@@ -0,0 +1,235 @@
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use async_std::fs::{self, OpenOptions};
+use async_std::io::WriteExt;
+
+use crate::entity::FSEntity;
+use crate::format::SizeFormat;
+
+/// What to do with the selected entries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Remove the entries for good
+    Delete,
+    /// Move the entries to the XDG trash of the user
+    Trash,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Action::Delete => "Delete",
+            Action::Trash => "Trash",
+        })
+    }
+}
+
+/// Entries selected for an action, checked against a scanned tree. Nothing
+/// touches the disk until [`Plan::execute`], so a plan doubles as a dry run
+#[derive(Clone, Debug)]
+pub struct Plan {
+    pub action: Action,
+    /// Selected paths and the bytes each of them accounts for in the tree
+    pub entries: Vec<(PathBuf, u64)>,
+}
+
+/// Outcome of the action on a single entry
+#[derive(Debug)]
+pub struct Applied {
+    pub path: PathBuf,
+    /// Bytes freed from the tree, or why the entry was left in place
+    pub result: io::Result<u64>,
+}
+
+impl Plan {
+    /// Selects `paths` below `root`. Fails with the first path that is not a
+    /// descendant of `root` in the scanned tree
+    pub fn new(root: &FSEntity, action: Action, paths: &[PathBuf]) -> Result<Self, PathBuf> {
+        let mut entries = vec![];
+        for path in paths {
+            match root.find(path) {
+                Some(entity) if entity.path() != root.path() => {
+                    entries.push((path.clone(), entity.size()))
+                }
+                _ => return Err(path.clone()),
+            }
+        }
+        Ok(Plan { action, entries })
+    }
+
+    /// Bytes the action frees if it succeeds for every entry
+    pub fn freed(&self) -> u64 {
+        self.entries.iter().map(|(_, size)| size).sum()
+    }
+
+    /// Applies the action and removes every entry it succeeded for from
+    /// `root`, updating the sizes of the folders above it
+    pub async fn execute(self, root: &mut FSEntity) -> Vec<Applied> {
+        let mut applied = vec![];
+        for (path, size) in self.entries {
+            let result = match self.action {
+                Action::Delete => delete(&path).await,
+                Action::Trash => trash(&path).await,
+            };
+            let result = result.map(|_| {
+                root.remove(&path);
+                size
+            });
+            applied.push(Applied { path, result });
+        }
+        applied
+    }
+}
+
+/// Writes one line per entry of the plan and the total it frees
+pub fn write_plan(out: &mut impl Write, plan: &Plan, format: &SizeFormat) -> io::Result<()> {
+    for (path, size) in &plan.entries {
+        writeln!(
+            out,
+            "{}\t[{}]\t{}",
+            plan.action,
+            format.format(*size),
+            path.display()
+        )?;
+    }
+    writeln!(out, "Total: {} to free", format.format(plan.freed()))
+}
+
+async fn delete(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path).await?.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+}
+
+/// Moves `path` to the home trash, or to the `.Trash-$uid` folder at the top
+/// of its file system when it lives elsewhere, following the freedesktop.org
+/// trash specification
+async fn trash(path: &Path) -> io::Result<()> {
+    let path = std::path::absolute(path)?;
+    let home = home_trash()?;
+
+    let device = fs::symlink_metadata(&path).await?.dev();
+    let trash = match fs::metadata(&home).await {
+        Ok(meta) if meta.dev() == device => home,
+        Err(_) if same_device(&home, device).await => home,
+        // SAFETY: getuid has no preconditions and cannot fail
+        _ => top_dir(&path, device)
+            .await
+            .join(format!(".Trash-{}", unsafe { libc::getuid() })),
+    };
+
+    let files = trash.join("files");
+    let info = trash.join("info");
+    fs::create_dir_all(&files).await?;
+    fs::create_dir_all(&info).await?;
+
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    for attempt in 0.. {
+        let mut unique = OsString::from(name);
+        if attempt != 0 {
+            unique.push(format!(".{attempt}"));
+        }
+        let mut info_name = unique.clone();
+        info_name.push(".trashinfo");
+        let info_path = info.join(&info_name);
+
+        // Creating the info file first reserves the name, as the spec requires
+        let mut info_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+            .await
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+        let content = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(&path),
+            local_timestamp()
+        );
+        info_file.write_all(content.as_bytes()).await?;
+        info_file.flush().await?;
+
+        return match fs::rename(&path, files.join(&unique)).await {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = fs::remove_file(&info_path).await;
+                Err(err)
+            }
+        };
+    }
+    unreachable!()
+}
+
+fn home_trash() -> io::Result<PathBuf> {
+    let data = match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".local/share"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?,
+    };
+    Ok(data.join("Trash"))
+}
+
+/// Whether the closest existing parent of `path` is on `device`
+async fn same_device(path: &Path, device: u64) -> bool {
+    for parent in path.ancestors() {
+        if let Ok(meta) = fs::metadata(parent).await {
+            return meta.dev() == device;
+        }
+    }
+    false
+}
+
+/// Top folder of the file system `path` lives on
+async fn top_dir(path: &Path, device: u64) -> PathBuf {
+    let mut top = path;
+    while let Some(parent) = top.parent() {
+        match fs::metadata(parent).await {
+            Ok(meta) if meta.dev() == device => top = parent,
+            _ => break,
+        }
+    }
+    top.to_owned()
+}
+
+fn percent_encode(path: &Path) -> String {
+    let mut out = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+/// Local time as `YYYY-MM-DDThh:mm:ss`
+fn local_timestamp() -> String {
+    // SAFETY: time and localtime_r only write into the values they are given
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&now, &mut tm);
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        )
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::entity::{FSEntity, TimeField};
+use crate::format::{escape_path, SizeFormat};
+use crate::json::write_one_or_many;
+use crate::largest::own_bytes;
+use crate::owners::Usage;
+use crate::report::ratio;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Age buckets with the age, in seconds, their entries are younger than
+const BUCKETS: &[(&str, i64)] = &[
+    ("<1 day", DAY),
+    ("<1 week", 7 * DAY),
+    ("<1 month", 30 * DAY),
+    ("<1 year", 365 * DAY),
+    ("older", i64::MAX),
+];
+
+/// Bucket of the entries whose metadata could not be read
+const UNKNOWN: &str = "unknown";
+
+/// Bytes and files of a tree per age, youngest first
+#[derive(Clone, Debug, Default)]
+pub struct Ages {
+    pub buckets: Vec<(&'static str, Usage)>,
+    /// Every byte counted in the buckets
+    pub total: Usage,
+}
+
+/// Seconds since the Unix epoch, negative before it
+pub fn unix_time(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+/// Sums the bytes and files below `root` by how long before `now` every
+/// entry was last modified or accessed, a folder adding its own blocks
+pub fn ages(root: &FSEntity, field: TimeField, now: SystemTime) -> Ages {
+    let now = unix_time(now);
+    let mut buckets = BUCKETS
+        .iter()
+        .map(|(name, _)| (*name, Usage::default()))
+        .collect::<Vec<_>>();
+    let mut unknown = Usage::default();
+    let mut total = Usage::default();
+
+    for (entity, own) in own_bytes(root) {
+        let usage = match entity.times {
+            Some(times) => {
+                let age = now.saturating_sub(times.get(field));
+                let index = BUCKETS
+                    .iter()
+                    .position(|&(_, younger)| age < younger)
+                    .unwrap_or(BUCKETS.len() - 1);
+                &mut buckets[index].1
+            }
+            None => &mut unknown,
+        };
+        for sum in [usage, &mut total] {
+            sum.size += own;
+            sum.files += u64::from(entity.is_file());
+        }
+    }
+
+    if unknown != Usage::default() {
+        buckets.push((UNKNOWN, unknown));
+    }
+    Ages { buckets, total }
+}
+
+/// Writes the size and file count of every age bucket
+pub fn write_ages(
+    out: &mut impl Write,
+    root: &FSEntity,
+    ages: &Ages,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}\t[{}]\t{} files",
+        root.path.display(),
+        format.format(ages.total.size),
+        ages.total.files
+    )?;
+    for (name, usage) in &ages.buckets {
+        writeln!(
+            out,
+            "AGE\t[{size} = {ratio:.2}%]\t{files} files\t{name}",
+            size = format.format(usage.size),
+            ratio = ratio(usage.size, ages.total.size),
+            files = usage.files,
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonAges {
+    path: String,
+    size: u64,
+    files: u64,
+    ages: Vec<JsonBucket>,
+}
+
+#[derive(Serialize)]
+struct JsonBucket {
+    name: &'static str,
+    size: u64,
+    files: u64,
+    percentage: f64,
+}
+
+/// Writes the age buckets of every root as a JSON object, or an array of
+/// them for several roots
+pub fn write_ages_json(out: &mut impl Write, roots: &[(&FSEntity, &Ages)]) -> io::Result<()> {
+    let reports = roots
+        .iter()
+        .map(|(root, ages)| JsonAges {
+            path: escape_path(&root.path),
+            size: ages.total.size,
+            files: ages.total.files,
+            ages: ages
+                .buckets
+                .iter()
+                .map(|(name, usage)| JsonBucket {
+                    name,
+                    size: usage.size,
+                    files: usage.files,
+                    percentage: ratio(usage.size, ages.total.size),
+                })
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    write_one_or_many(out, &reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::entity::{FSType, Times};
+    use crate::report::PrintOptions;
+
+    const NOW: i64 = 1000 * DAY;
+
+    fn aged(path: &str, size: u64, kind: FSType, modified: i64, accessed: i64) -> FSEntity {
+        FSEntity {
+            times: Some(Times {
+                modified: NOW - modified,
+                accessed: NOW - accessed,
+            }),
+            ..FSEntity::fixture(path, size, kind)
+        }
+    }
+
+    fn tree() -> FSEntity {
+        aged(
+            "t",
+            4,
+            FSType::Folder(vec![
+                aged("t/new", 10, FSType::File, 60, 60),
+                aged("t/old", 20, FSType::File, 40 * DAY, 60),
+                aged("t/ancient", 30, FSType::File, 400 * DAY, 400 * DAY),
+                FSEntity::fixture("t/unread", 5, FSType::File),
+            ]),
+            2 * DAY,
+            2 * DAY,
+        )
+    }
+
+    fn buckets(ages: &Ages) -> Vec<(&str, u64, u64)> {
+        ages.buckets
+            .iter()
+            .map(|(name, usage)| (*name, usage.size, usage.files))
+            .collect()
+    }
+
+    #[test]
+    fn unix_time_is_signed() {
+        assert_eq!(unix_time(UNIX_EPOCH + Duration::from_secs(5)), 5);
+        assert_eq!(unix_time(UNIX_EPOCH - Duration::from_secs(5)), -5);
+    }
+
+    #[test]
+    fn ages_sum_own_bytes_per_bucket() {
+        let now = UNIX_EPOCH + Duration::from_secs(NOW as u64);
+
+        let ages = ages(&tree(), TimeField::Modified, now);
+        assert_eq!(
+            buckets(&ages),
+            [
+                ("<1 day", 10, 1),
+                ("<1 week", 4, 0),
+                ("<1 month", 0, 0),
+                ("<1 year", 20, 1),
+                ("older", 30, 1),
+                (UNKNOWN, 5, 1),
+            ]
+        );
+        assert_eq!(ages.total, Usage { size: 69, files: 4 });
+
+        let accessed = super::ages(&tree(), TimeField::Accessed, now);
+        assert_eq!(
+            accessed.buckets[0],
+            ("<1 day", Usage { size: 30, files: 2 })
+        );
+    }
+
+    #[test]
+    fn older_than_hides_entries_with_anything_newer() {
+        let root = tree();
+        let options = PrintOptions {
+            older_than: Some(UNIX_EPOCH + Duration::from_secs((NOW - 30 * DAY) as u64)),
+            ..PrintOptions::default()
+        };
+        let visible = options.visible(root.children());
+        let paths = visible.iter().map(|entity| entity.path.to_str().unwrap());
+        assert_eq!(paths.collect::<Vec<_>>(), ["t/old", "t/ancient"]);
+    }
+}
@@ -0,0 +1,230 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::entity::{FSEntity, FSType};
+use crate::report::{ratio, PrintOptions};
+
+/// Cells of the percentage bar
+const BAR_WIDTH: usize = 10;
+
+/// Partial cells of the bar, by eighths
+const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+const RESET: &str = "\x1b[0m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[36m";
+const MAGENTA: &str = "\x1b[35m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+
+/// Colors of the sizes from this many bytes up, largest first
+const TIERS: &[(u64, &str)] = &[
+    (1024 * 1024 * 1024, RED),
+    (100 * 1024 * 1024, YELLOW),
+    (1024 * 1024, GREEN),
+];
+
+/// One line of the tree before the columns are aligned
+struct Row {
+    size: u64,
+    /// Share of the parent, `None` for what the parent does not count
+    ratio: Option<f64>,
+    branches: String,
+    name: String,
+    paint: &'static str,
+}
+
+/// Writes the tree of `root` with box drawing branches, right aligned sizes
+/// and a bar of the share of every entry in its folder. With `color` sizes
+/// are colored by how large they are and names by their kind
+pub(crate) fn write_boxes(
+    out: &mut impl Write,
+    root: &FSEntity,
+    options: &PrintOptions,
+    color: bool,
+) -> io::Result<()> {
+    let mut rows = vec![Row {
+        size: root.size,
+        ratio: Some(ratio(root.size, root.size)),
+        branches: String::new(),
+        name: options.path(&root.path),
+        paint: kind_color(&root.kind),
+    }];
+    collect(&mut rows, root, 0, "", options);
+
+    let sizes = rows
+        .iter()
+        .map(|row| options.size(row.size))
+        .collect::<Vec<_>>();
+    let width = sizes.iter().map(String::len).max().unwrap_or(0);
+
+    for (row, size) in rows.iter().zip(sizes) {
+        let tier = TIERS
+            .iter()
+            .find(|(from, _)| row.size >= *from)
+            .map_or("", |(_, tier)| tier);
+        let (percent, bar) = match row.ratio {
+            Some(ratio) => (format!("{ratio:.2}%"), bar(ratio)),
+            None => (String::new(), " ".repeat(BAR_WIDTH)),
+        };
+        writeln!(
+            out,
+            "{size} {percent:>7} {bar} {branches}{name}",
+            size = paint(&format!("{size:>width$}"), tier, color),
+            bar = paint(&bar, tier, color),
+            branches = row.branches,
+            name = paint(&row.name, row.paint, color),
+        )?;
+    }
+    Ok(())
+}
+
+fn collect(
+    rows: &mut Vec<Row>,
+    parent: &FSEntity,
+    level: u32,
+    indent: &str,
+    options: &PrintOptions,
+) {
+    if !options.descends(level) {
+        return;
+    }
+
+    let (children, collapsed) = options.rows(parent);
+    let excluded = parent.excluded_size != 0;
+    let count = children.len() + usize::from(collapsed.is_some()) + usize::from(excluded);
+    for (index, entity) in children.into_iter().enumerate() {
+        let last = index + 1 == count;
+        let name = entity
+            .path
+            .file_name()
+            .map(Path::new)
+            .unwrap_or(&entity.path);
+        let mut name = options.path(name);
+        if entity.is_folder() {
+            name.push('/');
+        }
+        rows.push(Row {
+            size: entity.size,
+            ratio: Some(ratio(entity.size, parent.size)),
+            branches: format!("{indent}{}", if last { "└── " } else { "├── " }),
+            name,
+            paint: kind_color(&entity.kind),
+        });
+
+        if entity.is_folder() {
+            let indent = format!("{indent}{}", if last { "    " } else { "│   " });
+            collect(rows, entity, level + 1, &indent, options);
+        }
+    }
+
+    if let Some(collapsed) = collapsed {
+        rows.push(Row {
+            size: collapsed.size,
+            ratio: Some(ratio(collapsed.size, parent.size)),
+            branches: format!("{indent}{}", if excluded { "├── " } else { "└── " }),
+            name: collapsed.name(options),
+            paint: DIM,
+        });
+    }
+
+    if excluded {
+        rows.push(Row {
+            size: parent.excluded_size,
+            ratio: None,
+            branches: format!("{indent}└── "),
+            name: "<excluded>".to_owned(),
+            paint: DIM,
+        });
+    }
+}
+
+fn kind_color(kind: &FSType) -> &'static str {
+    match kind {
+        FSType::Folder(_) => BOLD_BLUE,
+        FSType::Symlink => CYAN,
+        FSType::File => "",
+        FSType::MountPoint | FSType::Other(_) => MAGENTA,
+    }
+}
+
+/// `ratio` percent of the bar, in eighths of a cell
+fn bar(ratio: f64) -> String {
+    let eighths = ((ratio / 100.0 * (BAR_WIDTH * 8) as f64).round() as usize).min(BAR_WIDTH * 8);
+    let mut bar = "█".repeat(eighths / 8);
+    if !eighths.is_multiple_of(8) {
+        bar.push(EIGHTHS[eighths % 8]);
+    }
+    let cells = bar.chars().count();
+    bar.extend(std::iter::repeat_n(' ', BAR_WIDTH - cells));
+    bar
+}
+
+fn paint(text: &str, code: &str, color: bool) -> String {
+    match color && !code.is_empty() {
+        true => format!("{code}{text}{RESET}"),
+        false => text.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Threshold;
+
+    #[test]
+    fn bar_fills_eighths_of_cells() {
+        assert_eq!(bar(0.0), " ".repeat(BAR_WIDTH));
+        assert_eq!(bar(100.0), "█".repeat(BAR_WIDTH));
+        assert_eq!(bar(150.0), "█".repeat(BAR_WIDTH));
+        assert_eq!(bar(25.0), "██▌       ");
+        assert_eq!(bar(1.25), "▏         ");
+    }
+
+    #[test]
+    fn paint_only_with_color() {
+        assert_eq!(paint("a", RED, true), "\x1b[31ma\x1b[0m");
+        assert_eq!(paint("a", RED, false), "a");
+        assert_eq!(paint("a", "", true), "a");
+    }
+
+    #[test]
+    fn write_boxes_collapses_small_entries() {
+        let root = FSEntity {
+            excluded_size: 7,
+            ..FSEntity::fixture(
+                "t",
+                0,
+                FSType::Folder(vec![
+                    FSEntity::fixture(
+                        "t/a",
+                        0,
+                        FSType::Folder(vec![FSEntity::fixture("t/a/f", 60, FSType::File)]),
+                    ),
+                    FSEntity::fixture("t/b", 30, FSType::File),
+                    FSEntity::fixture("t/c", 6, FSType::File),
+                    FSEntity::fixture("t/d", 4, FSType::Symlink),
+                ]),
+            )
+        };
+        let options = PrintOptions {
+            collapse: Some(Threshold::Bytes(10)),
+            ..PrintOptions::default()
+        };
+        let mut out = vec![];
+        write_boxes(&mut out, &root, &options, false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+100 B 100.00% ██████████ t
+ 60 B  60.00% ██████     ├── a/
+ 60 B 100.00% ██████████ │   └── f
+ 30 B  30.00% ███        ├── b
+ 10 B  10.00% █          ├── <2 other entries: 10 B>
+  7 B                    └── <excluded>
+"
+        );
+    }
+}
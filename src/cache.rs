@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use async_std::fs::FileType;
+
+use crate::entity::{OtherKind, Owner, Times};
+use crate::snapshot::{invalid, read_bytes, read_varint, write_bytes, write_varint};
+
+/// First bytes of every index file
+const MAGIC: &[u8; 4] = b"WGHI";
+const VERSION: u8 = 3;
+
+/// Default folder of the directory index: `$XDG_CACHE_HOME/weights`, or
+/// `~/.cache/weights` when it is not set
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("weights"))
+}
+
+/// The parts of the metadata of an entry that its sizes are made of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
+    pub len: u64,
+    pub blocks: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: i64,
+    pub atime: i64,
+}
+
+impl Stat {
+    pub fn sizes(&self) -> (u64, u64) {
+        (self.len, self.blocks * 512)
+    }
+
+    pub fn owner(&self) -> Owner {
+        Owner {
+            uid: self.uid,
+            gid: self.gid,
+        }
+    }
+
+    pub fn times(&self) -> Times {
+        Times {
+            modified: self.mtime,
+            accessed: self.atime,
+        }
+    }
+}
+
+impl<M: MetadataExt> From<&M> for Stat {
+    fn from(meta: &M) -> Self {
+        Stat {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            nlink: meta.nlink(),
+            len: meta.size(),
+            blocks: meta.blocks(),
+            uid: meta.uid(),
+            gid: meta.gid(),
+            mtime: meta.mtime(),
+            atime: meta.atime(),
+        }
+    }
+}
+
+/// Type of an entry as its folder lists it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+    Other(OtherKind),
+}
+
+impl From<FileType> for EntryKind {
+    fn from(file_type: FileType) -> Self {
+        if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::Other(file_type.into())
+        }
+    }
+}
+
+/// An entry of a folder, read from disk or from the index
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Listed {
+    pub name: OsString,
+    pub kind: EntryKind,
+    /// Metadata of anything but a folder, once it was read
+    pub stat: Option<Stat>,
+}
+
+/// Identity and modification time of a folder; a folder whose key did not
+/// change since the previous scan still holds the same entries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct DirKey {
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl<M: MetadataExt> From<&M> for DirKey {
+    fn from(meta: &M) -> Self {
+        DirKey {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+        }
+    }
+}
+
+type Dirs = HashMap<PathBuf, (DirKey, Vec<Listed>)>;
+
+/// Listings of the folders of one root kept between scans, keyed by their
+/// path relative to the root
+pub(crate) struct DirCache {
+    file: PathBuf,
+    root: PathBuf,
+    validate: bool,
+    previous: Dirs,
+    current: Mutex<Dirs>,
+    reused: AtomicU64,
+    stale: Mutex<Vec<PathBuf>>,
+}
+
+impl DirCache {
+    /// Loads the index of `root` from `dir`, starting empty when there is none
+    /// or it cannot be read. With `validate` nothing is reused: every folder
+    /// is read and compared with its cached listing instead
+    pub fn load(dir: &Path, root: &Path, validate: bool) -> Self {
+        let canonical = root.canonicalize().unwrap_or_else(|_| root.to_owned());
+        let mut hasher = DefaultHasher::new();
+        canonical.hash(&mut hasher);
+        let file = dir.join(format!("{:016x}.idx", hasher.finish()));
+
+        let previous = File::open(&file)
+            .and_then(|input| read_index(&mut BufReader::new(input), &canonical))
+            .unwrap_or_default();
+
+        DirCache {
+            file,
+            root: root.to_owned(),
+            validate,
+            previous,
+            current: Mutex::default(),
+            reused: AtomicU64::default(),
+            stale: Mutex::default(),
+        }
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    /// Entries of the folder at `path` if it did not change since they were
+    /// cached; never reuses anything while validating. Only names and kinds
+    /// are reused: a file can grow without its folder changing, so the
+    /// metadata of every entry is read again
+    pub fn lookup(&self, path: &Path, key: DirKey) -> Option<Vec<Listed>> {
+        if self.validate {
+            return None;
+        }
+        let (cached_key, entries) = self.previous.get(self.relative(path))?;
+        if *cached_key != key {
+            return None;
+        }
+        self.reused.fetch_add(1, Ordering::Relaxed);
+        let entries = entries
+            .iter()
+            .map(|entry| Listed {
+                stat: None,
+                ..entry.clone()
+            })
+            .collect();
+        Some(entries)
+    }
+
+    /// Records the complete listing of a folder for the next scan. While
+    /// validating, a folder whose key is unchanged but whose entries differ
+    /// from the cached ones is reported as stale
+    pub fn store(&self, path: &Path, key: DirKey, mut entries: Vec<Listed>) {
+        let relative = self.relative(path).to_owned();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        if self.validate {
+            if let Some((cached_key, cached)) = self.previous.get(&relative) {
+                if *cached_key == key && !same_entries(cached, &entries) {
+                    self.stale.lock().unwrap().push(path.to_owned());
+                }
+            }
+        }
+        self.current
+            .lock()
+            .unwrap()
+            .insert(relative, (key, entries));
+    }
+
+    pub fn validating(&self) -> bool {
+        self.validate
+    }
+
+    pub fn reused(&self) -> u64 {
+        self.reused.load(Ordering::Relaxed)
+    }
+
+    pub fn take_stale(&self) -> Vec<PathBuf> {
+        let mut stale = std::mem::take(&mut *self.stale.lock().unwrap());
+        stale.sort();
+        stale
+    }
+
+    /// Replaces the index file with the folders listed during this scan
+    pub fn save(&self) -> io::Result<()> {
+        let canonical = self
+            .root
+            .canonicalize()
+            .unwrap_or_else(|_| self.root.clone());
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let temporary = self.file.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&temporary)?);
+        write_index(&mut out, &canonical, &self.current.lock().unwrap())?;
+        out.into_inner().map_err(|err| err.into_error())?;
+        fs::rename(&temporary, &self.file)
+    }
+}
+
+/// Entries equal in name and kind, and in metadata wherever both sides know
+/// it. Access times are left out: merely reading a file changes them
+fn same_entries(cached: &[Listed], current: &[Listed]) -> bool {
+    cached.len() == current.len()
+        && cached.iter().zip(current).all(|(a, b)| {
+            a.name == b.name
+                && a.kind == b.kind
+                && match (a.stat, b.stat) {
+                    (Some(a), Some(b)) => Stat { atime: 0, ..a } == Stat { atime: 0, ..b },
+                    _ => true,
+                }
+        })
+}
+
+fn kind_tag(kind: EntryKind) -> u8 {
+    match kind {
+        EntryKind::Dir => 0,
+        EntryKind::File => 1,
+        EntryKind::Symlink => 2,
+        EntryKind::Other(OtherKind::Socket) => 3,
+        EntryKind::Other(OtherKind::Fifo) => 4,
+        EntryKind::Other(OtherKind::BlockDevice) => 5,
+        EntryKind::Other(OtherKind::CharDevice) => 6,
+        EntryKind::Other(OtherKind::Unknown) => 7,
+    }
+}
+
+fn write_index(out: &mut impl Write, root: &Path, dirs: &Dirs) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    write_bytes(out, root.as_os_str().as_bytes())?;
+    write_varint(out, dirs.len() as u64)?;
+
+    for (path, (key, entries)) in dirs {
+        write_bytes(out, path.as_os_str().as_bytes())?;
+        for value in [key.dev, key.ino, key.mtime as u64, key.mtime_nsec as u64] {
+            write_varint(out, value)?;
+        }
+        write_varint(out, entries.len() as u64)?;
+        for entry in entries {
+            write_bytes(out, entry.name.as_bytes())?;
+            match entry.stat {
+                Some(stat) => {
+                    out.write_all(&[kind_tag(entry.kind) | 0x80])?;
+                    for value in [stat.dev, stat.ino, stat.nlink, stat.len, stat.blocks] {
+                        write_varint(out, value)?;
+                    }
+                    write_varint(out, stat.uid.into())?;
+                    write_varint(out, stat.gid.into())?;
+                    write_varint(out, stat.mtime as u64)?;
+                    write_varint(out, stat.atime as u64)?;
+                }
+                None => out.write_all(&[kind_tag(entry.kind)])?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_index(input: &mut impl Read, root: &Path) -> io::Result<Dirs> {
+    let mut header = [0; 5];
+    input.read_exact(&mut header)?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(invalid("not an index file"));
+    }
+    if read_bytes(input)? != root.as_os_str().as_bytes() {
+        return Err(invalid("index of another root"));
+    }
+
+    let mut dirs = HashMap::new();
+    for _ in 0..read_varint(input)? {
+        let path = PathBuf::from(OsString::from_vec(read_bytes(input)?));
+        let key = DirKey {
+            dev: read_varint(input)?,
+            ino: read_varint(input)?,
+            mtime: read_varint(input)? as i64,
+            mtime_nsec: read_varint(input)? as i64,
+        };
+
+        let count = read_varint(input)?;
+        let mut entries = Vec::with_capacity(count.min(1 << 16) as usize);
+        for _ in 0..count {
+            let name = OsString::from_vec(read_bytes(input)?);
+            let mut tag = [0];
+            input.read_exact(&mut tag)?;
+            let kind = match tag[0] & 0x7f {
+                0 => EntryKind::Dir,
+                1 => EntryKind::File,
+                2 => EntryKind::Symlink,
+                3 => EntryKind::Other(OtherKind::Socket),
+                4 => EntryKind::Other(OtherKind::Fifo),
+                5 => EntryKind::Other(OtherKind::BlockDevice),
+                6 => EntryKind::Other(OtherKind::CharDevice),
+                7 => EntryKind::Other(OtherKind::Unknown),
+                _ => return Err(invalid("unknown entry kind")),
+            };
+            let stat = match tag[0] & 0x80 != 0 {
+                true => Some(Stat {
+                    dev: read_varint(input)?,
+                    ino: read_varint(input)?,
+                    nlink: read_varint(input)?,
+                    len: read_varint(input)?,
+                    blocks: read_varint(input)?,
+                    uid: read_varint(input)? as u32,
+                    gid: read_varint(input)? as u32,
+                    mtime: read_varint(input)? as i64,
+                    atime: read_varint(input)? as i64,
+                }),
+                false => None,
+            };
+            entries.push(Listed { name, kind, stat });
+        }
+        dirs.insert(path, (key, entries));
+    }
+    Ok(dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn stat(ino: u64, len: u64) -> Stat {
+        Stat {
+            dev: 2049,
+            ino,
+            nlink: 1,
+            len,
+            blocks: len.div_ceil(512),
+            uid: 1000,
+            gid: u32::MAX,
+            mtime: 1_700_000_000,
+            atime: -86_400,
+        }
+    }
+
+    fn listed(name: &str, kind: EntryKind, stat: Option<Stat>) -> Listed {
+        Listed {
+            name: name.into(),
+            kind,
+            stat,
+        }
+    }
+
+    fn dirs() -> Dirs {
+        let key = DirKey {
+            dev: 2049,
+            ino: 12,
+            mtime: -5,
+            mtime_nsec: 999_999_999,
+        };
+        let entries = vec![
+            listed("file", EntryKind::File, Some(stat(13, 5000))),
+            listed("sub", EntryKind::Dir, None),
+            listed("link", EntryKind::Symlink, Some(stat(14, 4))),
+            listed("fifo", EntryKind::Other(OtherKind::Fifo), None),
+            Listed {
+                name: OsString::from_vec(b"\xff".to_vec()),
+                kind: EntryKind::Other(OtherKind::Unknown),
+                stat: None,
+            },
+        ];
+        let mut dirs = Dirs::new();
+        dirs.insert(PathBuf::new(), (key, entries));
+        dirs.insert(PathBuf::from("sub"), (key, vec![]));
+        dirs
+    }
+
+    #[test]
+    fn index_round_trip() {
+        let root = Path::new("/data");
+        let mut out = vec![];
+        write_index(&mut out, root, &dirs()).unwrap();
+        assert_eq!(read_index(&mut Cursor::new(out), root).unwrap(), dirs());
+    }
+
+    #[test]
+    fn read_index_rejects_other_roots_and_versions() {
+        let mut out = vec![];
+        write_index(&mut out, Path::new("/data"), &dirs()).unwrap();
+        assert!(read_index(&mut Cursor::new(&out), Path::new("/other")).is_err());
+
+        out[4] = VERSION - 1;
+        assert!(read_index(&mut Cursor::new(&out), Path::new("/data")).is_err());
+        assert!(read_index(&mut Cursor::new(b"WGHT"), Path::new("/data")).is_err());
+    }
+
+    fn loaded(validate: bool) -> DirCache {
+        DirCache {
+            file: PathBuf::new(),
+            root: PathBuf::from("/data"),
+            validate,
+            previous: dirs(),
+            current: Mutex::default(),
+            reused: AtomicU64::default(),
+            stale: Mutex::default(),
+        }
+    }
+
+    #[test]
+    fn lookup_reuses_names_but_not_metadata() {
+        let (key, cached) = dirs().remove(Path::new("")).unwrap();
+        let cache = loaded(false);
+        let entries = cache.lookup(Path::new("/data"), key).unwrap();
+        assert_eq!(cache.reused(), 1);
+        assert!(entries.iter().all(|entry| entry.stat.is_none()));
+        let names = |entries: &[Listed]| {
+            entries
+                .iter()
+                .map(|entry| (entry.name.clone(), entry.kind))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&entries), names(&cached));
+
+        let changed = DirKey { mtime: 0, ..key };
+        assert!(cache.lookup(Path::new("/data"), changed).is_none());
+        assert!(cache.lookup(Path::new("/data/sub"), changed).is_none());
+        assert!(loaded(true).lookup(Path::new("/data"), key).is_none());
+    }
+
+    #[test]
+    fn same_entries_ignores_access_times() {
+        let read = vec![listed("file", EntryKind::File, Some(stat(13, 5000)))];
+        let mut touched = read.clone();
+        touched[0].stat.as_mut().unwrap().atime += 60;
+        assert!(same_entries(&read, &touched));
+
+        let mut grown = read.clone();
+        grown[0].stat.as_mut().unwrap().len += 1;
+        assert!(!same_entries(&read, &grown));
+        assert!(!same_entries(&read, &[]));
+    }
+}
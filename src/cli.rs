@@ -0,0 +1,553 @@
+use std::fs::read_to_string;
+use std::io::{stdout, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use crossterm::terminal;
+use weights::{
+    parse_age, parse_block_size, parse_size, parse_threshold, Categories, DuOptions, Scanner,
+    SizeFormat, SizeMode, Threshold, TreeStyle, Units,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortBy {
+    /// Folders first, then largest entries first
+    #[default]
+    Size,
+    /// Alphabetical by path
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimeField {
+    /// Last change of the contents
+    #[default]
+    Modified,
+    /// Last read, as far as the mount options let the kernel record it
+    Accessed,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SizeUnits {
+    /// Powers of 1024: KiB, MiB, GiB up to EiB
+    #[default]
+    Iec,
+    /// Powers of 1000: kB, MB, GB up to EB
+    Si,
+    /// Exact byte counts
+    Bytes,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Style {
+    /// Tab separated lines with full paths, easy to parse
+    #[default]
+    Plain,
+    /// Box drawing branches, aligned sizes and percentage bars
+    Boxes,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Human readable indented tree
+    #[default]
+    Text,
+    /// Nested JSON document of the whole tree
+    Json,
+    /// One flat JSON record per line, written while walking the tree
+    Ndjson,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum HardLinks {
+    /// Count every inode once, on the first path it is seen through
+    #[default]
+    First,
+    /// Give every name an equal share of the size of the inode
+    Split,
+    /// Count the full size for every name
+    All,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compare two scans, each one a snapshot file or a folder scanned now
+    Diff {
+        /// Older side: a snapshot saved with --save, or a folder
+        old: PathBuf,
+        /// Newer side: a snapshot saved with --save, or a folder
+        new: PathBuf,
+    },
+    /// Find files with the same contents and the bytes a single copy would free
+    Duplicates {
+        /// Root paths to search
+        #[arg(default_value = ".")]
+        paths: Vec<PathBuf>,
+        /// Report every inode under one name, so hard links are not duplicates
+        #[arg(long)]
+        ignore_hard_links: bool,
+    },
+}
+
+/// Disk/Directory space usage report
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Root paths to scan
+    #[arg(default_value = ".")]
+    pub paths: Vec<PathBuf>,
+
+    /// Do not print entries deeper than this level below the root
+    #[arg(short = 'd', long, global = true)]
+    pub max_depth: Option<u32>,
+
+    /// Only print the N largest entries of every folder, or the N largest changes of a diff
+    #[arg(short = 'n', long, value_name = "N", global = true)]
+    pub top: Option<usize>,
+
+    /// Units sizes are printed in
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub units: SizeUnits,
+
+    /// Print sizes as a number of blocks of SIZE, like du (e.g. K, M, G, KB, MiB, 4K, 512)
+    #[arg(short = 'B', long, value_parser = parse_block_size, value_name = "SIZE", conflicts_with = "units", global = true)]
+    pub block_size: Option<Units>,
+
+    /// Digits after the decimal point of scaled sizes
+    #[arg(long, value_name = "N", default_value_t = 2, global = true)]
+    pub precision: usize,
+
+    /// Shorten paths to N columns, 0 to never shorten them [default: what the terminal leaves next to the sizes, 50 when not writing to a terminal]
+    #[arg(long, value_name = "N", global = true)]
+    pub path_width: Option<usize>,
+
+    /// Hide entries smaller than this size (e.g. 4096, 10K, 1.5M, 2G)
+    #[arg(short = 'm', long, value_parser = parse_size, value_name = "SIZE", global = true)]
+    pub min_size: Option<u64>,
+
+    /// Merge the entries of a folder smaller than SIZE, or than PERCENT of the folder, into one line of the tree (e.g. 1M, 0.5%)
+    #[arg(long, value_parser = parse_threshold, value_name = "SIZE|PERCENT")]
+    pub collapse: Option<Threshold>,
+
+    /// Order of the entries inside every folder
+    #[arg(short, long, value_enum, default_value_t, global = true)]
+    pub sort: SortBy,
+
+    /// Print a flat list of the N largest files and the N largest folders by own size instead of the tree
+    #[arg(long, value_name = "N")]
+    pub largest: Option<usize>,
+
+    /// Print the bytes and number of files per category and per extension instead of the tree
+    #[arg(long, conflicts_with = "largest")]
+    pub by_type: bool,
+
+    /// Count the extensions in category NAME for --by-type, e.g. `media=jpg,png` (repeatable)
+    #[arg(long, value_name = "NAME=EXT,...")]
+    pub category: Vec<String>,
+
+    /// Tell the type of files without an extension from their first bytes for --by-type
+    #[arg(long)]
+    pub sniff: bool,
+
+    /// Print the bytes and number of files per user and per group, overall and per top-level folder, instead of the tree
+    #[arg(long, conflicts_with_all = ["largest", "by_type"])]
+    pub by_owner: bool,
+
+    /// Print the bytes and number of files per age (<1 day, <1 week, <1 month, <1 year, older) instead of the tree
+    #[arg(long, conflicts_with_all = ["largest", "by_type", "by_owner"])]
+    pub by_age: bool,
+
+    /// Only print entries with nothing below them newer than AGE (e.g. 12h, 180d, 2w, 1y)
+    #[arg(long, value_parser = parse_age, value_name = "AGE")]
+    pub older_than: Option<Duration>,
+
+    /// Time the age of an entry is measured by for --by-age and --older-than
+    #[arg(long, value_enum, default_value_t)]
+    pub time: TimeField,
+
+    /// Follow symbolic links, counting every folder at most once
+    #[arg(short = 'L', long, global = true)]
+    pub follow_symlinks: bool,
+
+    /// Count the length of the contents (the default)
+    #[arg(long, conflicts_with = "disk_usage", global = true)]
+    pub apparent_size: bool,
+
+    /// Count the blocks allocated on disk, like `du`
+    #[arg(long, global = true)]
+    pub disk_usage: bool,
+
+    /// Stay on the file system of each root, skipping mount points
+    #[arg(short = 'x', long, global = true)]
+    pub one_file_system: bool,
+
+    /// List the mount points met during the scan with their file system type
+    #[arg(long)]
+    pub list_mounts: bool,
+
+    /// Leave out entries whose name or relative path matches the glob (repeatable)
+    #[arg(short, long, value_name = "GLOB", global = true)]
+    pub exclude: Vec<String>,
+
+    /// Read exclude globs from FILE, one per line
+    #[arg(long, value_name = "FILE", global = true)]
+    pub exclude_from: Option<PathBuf>,
+
+    /// Only count files whose name or relative path matches the glob (repeatable)
+    #[arg(short, long, value_name = "GLOB", global = true)]
+    pub include: Vec<String>,
+
+    /// Honor .gitignore and .ignore files in every scanned folder
+    #[arg(long, global = true)]
+    pub gitignore: bool,
+
+    /// Print the bytes left out by the filters as an EXCLUDED line per folder
+    #[arg(long)]
+    pub show_excluded: bool,
+
+    /// Maximum number of folders read at the same time [default: half the open file limit]
+    #[arg(short, long, value_name = "N", global = true)]
+    pub jobs: Option<usize>,
+
+    /// How files with several hard links are counted
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub hard_links: HardLinks,
+
+    /// Read every folder instead of reusing the cached entries of unchanged ones
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// Read every folder and list the cached ones that no longer match the disk
+    #[arg(long, conflicts_with = "no_cache", global = true)]
+    pub validate_cache: bool,
+
+    /// Only print the error summary, not every failure
+    #[arg(short, long, global = true)]
+    pub quiet_errors: bool,
+
+    /// Browse the scanned tree in a full screen terminal interface
+    #[arg(short = 'I', long)]
+    pub interactive: bool,
+
+    /// Delete PATH from disk after the scan, once confirmed (repeatable)
+    #[arg(long, value_name = "PATH")]
+    pub delete: Vec<PathBuf>,
+
+    /// Move PATH to the trash after the scan, once confirmed (repeatable)
+    #[arg(long, value_name = "PATH", conflicts_with = "delete")]
+    pub trash: Vec<PathBuf>,
+
+    /// Only print what --delete, --trash or the interactive actions would free
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Scan PATH, then keep the report up to date as its files change
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["paths", "interactive", "save"])]
+    pub watch: Option<PathBuf>,
+
+    /// Seconds between two reports of --watch, and between two full scans
+    /// when the folders cannot be watched
+    #[arg(long, value_name = "SECONDS", default_value_t = 2)]
+    pub interval: u64,
+
+    /// Save the scan as a snapshot FILE that `diff` can compare later
+    #[arg(long, value_name = "FILE")]
+    pub save: Option<PathBuf>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// Drawing of the text tree; colors are only used on a terminal without NO_COLOR set
+    #[arg(long, value_enum, default_value_t)]
+    pub style: Style,
+
+    /// Print SIZE<TAB>PATH lines like GNU du, taking its options instead (see --du --help)
+    #[arg(long)]
+    pub du: bool,
+}
+
+/// Whether --du comes before the end of the options, in which case the
+/// command line is parsed as [`DuArgs`]
+pub fn du_requested() -> bool {
+    std::env::args_os()
+        .skip(1)
+        .take_while(|arg| arg != "--")
+        .any(|arg| arg == "--du")
+}
+
+/// Summarize disk usage like GNU du, from a concurrent scan
+#[derive(Parser, Debug)]
+#[command(name = "weights --du", version, disable_help_flag = true)]
+pub struct DuArgs {
+    /// Print SIZE<TAB>PATH lines like GNU du
+    #[arg(long, required = true)]
+    pub du: bool,
+
+    /// Files and folders to summarize
+    #[arg(default_value = ".")]
+    pub paths: Vec<PathBuf>,
+
+    /// Write counts for all files, not just folders
+    #[arg(short, long, conflicts_with = "summarize")]
+    pub all: bool,
+
+    /// Display only a total for each argument
+    #[arg(short, long, conflicts_with = "max_depth")]
+    pub summarize: bool,
+
+    /// Print the total for a folder or file only if it is N or fewer levels below the argument
+    #[arg(short = 'd', long, value_name = "N")]
+    pub max_depth: Option<u32>,
+
+    /// Produce a grand total
+    #[arg(short = 'c', long)]
+    pub total: bool,
+
+    /// Print sizes in human readable format (e.g. 1K 234M 2G)
+    #[arg(short, long, conflicts_with_all = ["si", "k", "m", "bytes", "block_size"])]
+    pub human_readable: bool,
+
+    /// Like -h, but use powers of 1000 not 1024
+    #[arg(long, conflicts_with_all = ["k", "m", "bytes", "block_size"])]
+    pub si: bool,
+
+    /// Like --block-size=1K
+    #[arg(short, conflicts_with_all = ["m", "bytes", "block_size"])]
+    pub k: bool,
+
+    /// Like --block-size=1M
+    #[arg(short, conflicts_with_all = ["bytes", "block_size"])]
+    pub m: bool,
+
+    /// Equivalent to --apparent-size --block-size=1
+    #[arg(short, long, conflicts_with = "block_size")]
+    pub bytes: bool,
+
+    /// Scale sizes by SIZE before printing them (e.g. M, KB, 4K)
+    #[arg(short = 'B', long, value_parser = parse_block_size, value_name = "SIZE")]
+    pub block_size: Option<Units>,
+
+    /// Print apparent sizes rather than disk usage
+    #[arg(long)]
+    pub apparent_size: bool,
+
+    /// Count sizes many times if hard linked
+    #[arg(short = 'l', long)]
+    pub count_links: bool,
+
+    /// Dereference all symbolic links
+    #[arg(short = 'L', long)]
+    pub dereference: bool,
+
+    /// Skip folders on different file systems
+    #[arg(short = 'x', long)]
+    pub one_file_system: bool,
+
+    /// Exclude files that match the glob (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Exclude files that match any glob in FILE
+    #[arg(short = 'X', long, value_name = "FILE")]
+    pub exclude_from: Option<PathBuf>,
+
+    /// Print help
+    #[arg(long, action = ArgAction::Help)]
+    pub help: Option<bool>,
+}
+
+impl DuArgs {
+    pub fn options(&self) -> DuOptions {
+        let units = if self.human_readable {
+            Units::Iec
+        } else if self.si {
+            Units::Si
+        } else if self.bytes {
+            Units::Bytes
+        } else if let Some(block) = &self.block_size {
+            block.clone()
+        } else {
+            Units::Block {
+                size: if self.m { 1024 * 1024 } else { 1024 },
+                suffix: String::new(),
+            }
+        };
+        DuOptions {
+            all: self.all,
+            max_depth: if self.summarize {
+                Some(0)
+            } else {
+                self.max_depth
+            },
+            total: self.total,
+            units,
+        }
+    }
+
+    /// Scanner measuring sizes the way du does: disk usage, every inode once.
+    /// The cache is never used, so every size is read from the disk
+    pub fn scanner(&self) -> Result<Scanner, String> {
+        let size_mode = match self.apparent_size || self.bytes {
+            true => SizeMode::Apparent,
+            false => SizeMode::Disk,
+        };
+        let hard_links = match self.count_links {
+            true => weights::HardLinks::All,
+            false => weights::HardLinks::First,
+        };
+        let mut scanner = Scanner::new()
+            .sort(weights::SortBy::Name)
+            .follow_symlinks(self.dereference)
+            .hard_links(hard_links)
+            .one_file_system(self.one_file_system)
+            .size_mode(size_mode);
+        for pattern in &excludes(&self.exclude, self.exclude_from.as_deref())? {
+            scanner = scanner.exclude(pattern).map_err(|err| err.to_string())?;
+        }
+        Ok(scanner)
+    }
+}
+
+/// Columns taken by the type and sizes before the path on a line of the tree
+const SIZE_COLUMNS: usize = 76;
+
+/// Narrowest paths are shortened to on a small terminal
+const MIN_PATH_WIDTH: usize = 20;
+
+/// Width paths are shortened to when not writing to a terminal
+const DEFAULT_PATH_WIDTH: usize = 50;
+
+impl Args {
+    /// How sizes are printed, from --units, --block-size and --precision
+    pub fn size_format(&self) -> SizeFormat {
+        let units = match (&self.block_size, self.units) {
+            (Some(block), _) => block.clone(),
+            (None, SizeUnits::Iec) => Units::Iec,
+            (None, SizeUnits::Si) => Units::Si,
+            (None, SizeUnits::Bytes) => Units::Bytes,
+        };
+        SizeFormat {
+            units,
+            precision: self.precision,
+        }
+    }
+
+    /// Drawing of the text tree for --style, colored on a terminal unless
+    /// `NO_COLOR` is set
+    pub fn tree_style(&self) -> TreeStyle {
+        let color = stdout().is_terminal()
+            && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+        match self.style {
+            Style::Plain => TreeStyle::Plain,
+            Style::Boxes => TreeStyle::Boxes { color },
+        }
+    }
+
+    /// Columns paths are shortened to, `None` to print them whole
+    pub fn path_width(&self) -> Option<usize> {
+        match self.path_width {
+            Some(0) => None,
+            Some(width) => Some(width),
+            None if stdout().is_terminal() => terminal::size().ok().map(|(columns, _)| {
+                (columns as usize)
+                    .saturating_sub(SIZE_COLUMNS)
+                    .max(MIN_PATH_WIDTH)
+            }),
+            None => Some(DEFAULT_PATH_WIDTH),
+        }
+    }
+
+    pub fn size_mode(&self) -> SizeMode {
+        match self.disk_usage {
+            true => SizeMode::Disk,
+            false => SizeMode::Apparent,
+        }
+    }
+
+    /// Built in categories with those of --category on top
+    pub fn categories(&self) -> Result<Categories, String> {
+        let mut categories = Categories::default();
+        for definition in &self.category {
+            categories.parse(definition)?;
+        }
+        Ok(categories)
+    }
+
+    /// Scanner configured from the scan related options
+    pub fn scanner(&self) -> Result<Scanner, String> {
+        let mut scanner = Scanner::new()
+            .sort(self.sort.into())
+            .follow_symlinks(self.follow_symlinks)
+            .hard_links(self.hard_links.into())
+            .one_file_system(self.one_file_system)
+            .size_mode(self.size_mode())
+            .gitignore(self.gitignore)
+            .report_excluded(self.show_excluded)
+            .validate_cache(self.validate_cache);
+        if !self.no_cache {
+            scanner = scanner.cache(weights::default_cache_dir());
+        }
+        if let Some(jobs) = self.jobs {
+            scanner = scanner.jobs(jobs);
+        }
+
+        for pattern in &excludes(&self.exclude, self.exclude_from.as_deref())? {
+            scanner = scanner.exclude(pattern).map_err(|err| err.to_string())?;
+        }
+        for pattern in &self.include {
+            scanner = scanner.include(pattern).map_err(|err| err.to_string())?;
+        }
+        Ok(scanner)
+    }
+}
+
+/// Globs of --exclude followed by those of the --exclude-from file, one per
+/// line, skipping blank lines and `#` comments
+fn excludes(exclude: &[String], exclude_from: Option<&Path>) -> Result<Vec<String>, String> {
+    let mut excludes = exclude.to_vec();
+    if let Some(file) = exclude_from {
+        let content =
+            read_to_string(file).map_err(|err| format!("Reading {}: {err}", file.display()))?;
+        excludes.extend(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_owned),
+        );
+    }
+    Ok(excludes)
+}
+
+impl From<SortBy> for weights::SortBy {
+    fn from(sort: SortBy) -> Self {
+        match sort {
+            SortBy::Size => weights::SortBy::Size,
+            SortBy::Name => weights::SortBy::Name,
+        }
+    }
+}
+
+impl From<TimeField> for weights::TimeField {
+    fn from(time: TimeField) -> Self {
+        match time {
+            TimeField::Modified => weights::TimeField::Modified,
+            TimeField::Accessed => weights::TimeField::Accessed,
+        }
+    }
+}
+
+impl From<HardLinks> for weights::HardLinks {
+    fn from(hard_links: HardLinks) -> Self {
+        match hard_links {
+            HardLinks::First => weights::HardLinks::First,
+            HardLinks::Split => weights::HardLinks::Split,
+            HardLinks::All => weights::HardLinks::All,
+        }
+    }
+}
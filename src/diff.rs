@@ -0,0 +1,280 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::entity::FSEntity;
+use crate::report::PrintOptions;
+
+/// How an entry differs between two scans
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Only in the newer scan
+    Added,
+    /// Only in the older scan
+    Removed,
+    /// Larger in the newer scan
+    Grown,
+    /// Smaller in the newer scan
+    Shrunk,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Change::Added => "ADDED",
+            Change::Removed => "REMOVED",
+            Change::Grown => "GROWN",
+            Change::Shrunk => "SHRUNK",
+        })
+    }
+}
+
+/// An entry whose size differs between two scans. Folders are reported with
+/// the change of everything below them
+#[derive(Clone, Debug)]
+pub struct Delta {
+    /// Path relative to the roots of the scans
+    pub path: PathBuf,
+    pub change: Change,
+    pub old_size: u64,
+    pub new_size: u64,
+    /// Levels below the roots, starting at 0 for their children
+    pub depth: u32,
+}
+
+impl Delta {
+    /// Bytes gained, negative when the entry shrunk or was removed
+    pub fn delta(&self) -> i128 {
+        self.new_size as i128 - self.old_size as i128
+    }
+}
+
+/// Compares the trees of two scans of the same root, matching entries by
+/// their path relative to the root. Added and removed folders are reported
+/// once, without their contents; below changed folders the entries that
+/// changed are reported too, down to `options.max_depth`. The list is flat and
+/// sorted by the size of the change, largest first, so an entry is not
+/// necessarily next to its folder: [`Delta::depth`] tells how deep it is
+pub fn diff(old: &FSEntity, new: &FSEntity, options: &PrintOptions) -> Vec<Delta> {
+    let mut deltas = vec![];
+    compare(old, new, Path::new(""), 0, options, &mut deltas);
+    deltas.retain(|delta| delta.delta().unsigned_abs() >= options.min_size as u128);
+    deltas.sort_by_key(|delta| Reverse(delta.delta().unsigned_abs()));
+    if let Some(top) = options.top {
+        deltas.truncate(top);
+    }
+    deltas
+}
+
+fn compare(
+    old: &FSEntity,
+    new: &FSEntity,
+    relative: &Path,
+    level: u32,
+    options: &PrintOptions,
+    deltas: &mut Vec<Delta>,
+) {
+    if !options.descends(level) {
+        return;
+    }
+
+    let delta = |entity: &FSEntity, change, old_size, new_size| Delta {
+        path: relative.join(entity.path.file_name().unwrap_or_default()),
+        change,
+        old_size,
+        new_size,
+        depth: level,
+    };
+
+    let (old_children, new_children) = (by_name(old), by_name(new));
+
+    for child in old.children() {
+        if !new_children.contains_key(&child.path.file_name()) {
+            deltas.push(delta(child, Change::Removed, child.size, 0));
+        }
+    }
+
+    for child in new.children() {
+        let name = child.path.file_name();
+        let Some(before) = old_children.get(&name) else {
+            deltas.push(delta(child, Change::Added, 0, child.size));
+            continue;
+        };
+
+        if child.size != before.size {
+            let change = match child.size > before.size {
+                true => Change::Grown,
+                false => Change::Shrunk,
+            };
+            deltas.push(delta(child, change, before.size, child.size));
+        }
+        if child.is_folder() && before.is_folder() {
+            let path = relative.join(name.unwrap_or_default());
+            compare(before, child, &path, level + 1, options, deltas);
+        }
+    }
+}
+
+fn by_name(folder: &FSEntity) -> HashMap<Option<&OsStr>, &FSEntity> {
+    folder
+        .children()
+        .iter()
+        .map(|child| (child.path.file_name(), child))
+        .collect()
+}
+
+/// Writes the changes from `old` to `new`, headed by the change of the roots
+pub fn write_diff(
+    out: &mut impl Write,
+    old: &FSEntity,
+    new: &FSEntity,
+    options: &PrintOptions,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{} -> {}\t[{} -> {} = {}]",
+        old.path.display(),
+        new.path.display(),
+        options.size(old.size),
+        options.size(new.size),
+        signed_size(new.size as i128 - old.size as i128, options),
+    )?;
+
+    for delta in diff(old, new, options) {
+        writeln!(
+            out,
+            "{change}\t[{size}]\t[{old} -> {new}]\t{path}",
+            change = delta.change,
+            size = signed_size(delta.delta(), options),
+            old = options.size(delta.old_size),
+            new = options.size(delta.new_size),
+            path = options.path(&new.path.join(&delta.path)),
+        )?;
+    }
+    Ok(())
+}
+
+fn signed_size(delta: i128, options: &PrintOptions) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!("{sign}{}", options.size(delta.unsigned_abs() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::FSType;
+
+    fn trees() -> (FSEntity, FSEntity) {
+        let old = FSEntity::fixture(
+            "old",
+            0,
+            FSType::Folder(vec![
+                FSEntity::fixture(
+                    "old/a",
+                    0,
+                    FSType::Folder(vec![
+                        FSEntity::fixture("old/a/f", 10, FSType::File),
+                        FSEntity::fixture("old/a/g", 5, FSType::File),
+                    ]),
+                ),
+                FSEntity::fixture("old/gone", 3, FSType::File),
+                FSEntity::fixture("old/same", 1, FSType::File),
+            ]),
+        );
+        let new = FSEntity::fixture(
+            "new",
+            0,
+            FSType::Folder(vec![
+                FSEntity::fixture(
+                    "new/a",
+                    0,
+                    FSType::Folder(vec![
+                        FSEntity::fixture("new/a/f", 20, FSType::File),
+                        FSEntity::fixture("new/a/g", 5, FSType::File),
+                        FSEntity::fixture("new/a/n", 7, FSType::File),
+                    ]),
+                ),
+                FSEntity::fixture("new/same", 1, FSType::File),
+                FSEntity::fixture(
+                    "new/d",
+                    0,
+                    FSType::Folder(vec![FSEntity::fixture("new/d/x", 4, FSType::File)]),
+                ),
+            ]),
+        );
+        (old, new)
+    }
+
+    fn summary(deltas: &[Delta]) -> Vec<(&str, Change, i128, u32)> {
+        deltas
+            .iter()
+            .map(|delta| {
+                let path = delta.path.to_str().unwrap();
+                (path, delta.change, delta.delta(), delta.depth)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_matches_entries_by_relative_path() {
+        let (old, new) = trees();
+        let deltas = diff(&old, &new, &PrintOptions::default());
+        assert_eq!(
+            summary(&deltas),
+            [
+                ("a", Change::Grown, 17, 0),
+                ("a/f", Change::Grown, 10, 1),
+                ("a/n", Change::Added, 7, 1),
+                ("d", Change::Added, 4, 0),
+                ("gone", Change::Removed, -3, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_honors_depth_size_and_count_limits() {
+        let (old, new) = trees();
+        let options = PrintOptions {
+            max_depth: Some(1),
+            ..PrintOptions::default()
+        };
+        let paths = |deltas: Vec<Delta>| {
+            deltas
+                .into_iter()
+                .map(|delta| delta.path)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(diff(&old, &new, &options)),
+            ["a", "d", "gone"].map(PathBuf::from)
+        );
+
+        let options = PrintOptions {
+            min_size: 5,
+            top: Some(2),
+            ..PrintOptions::default()
+        };
+        assert_eq!(
+            paths(diff(&old, &new, &options)),
+            ["a", "a/f"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn write_diff_signs_the_changes() {
+        let (old, new) = trees();
+        let options = PrintOptions {
+            top: Some(1),
+            ..PrintOptions::default()
+        };
+        let mut out = vec![];
+        write_diff(&mut out, &new, &old, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "new -> old\t[37 B -> 19 B = -18 B]\nSHRUNK\t[-17 B]\t[32 B -> 15 B]\told/a\n"
+        );
+    }
+}
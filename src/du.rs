@@ -0,0 +1,275 @@
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+
+use crate::entity::{FSEntity, FSType};
+use crate::error::ScanOp;
+use crate::format::Units;
+
+/// Suffixes of the human readable sizes of `du -h`
+const HUMAN: &[&str] = &["K", "M", "G", "T", "P", "E"];
+
+/// What [`write_du`] prints, mirroring the options of GNU `du`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuOptions {
+    /// Print files too, not only folders (`-a`)
+    pub all: bool,
+    /// Do not print entries deeper than this below their root (`-d`); `-s`
+    /// is a depth of 0
+    pub max_depth: Option<u32>,
+    /// End with the sum of every root (`-c`)
+    pub total: bool,
+    /// `Iec` and `Si` are the human readable sizes of `-h` and `--si`;
+    /// blocks are rounded up
+    pub units: Units,
+}
+
+impl Default for DuOptions {
+    /// Folders only, in blocks of 1024 bytes
+    fn default() -> Self {
+        DuOptions {
+            all: false,
+            max_depth: None,
+            total: false,
+            units: Units::Block {
+                size: 1024,
+                suffix: String::new(),
+            },
+        }
+    }
+}
+
+impl DuOptions {
+    fn size(&self, size: u64) -> String {
+        match &self.units {
+            Units::Iec => human(size, 1024),
+            Units::Si => human(size, 1000),
+            Units::Bytes => size.to_string(),
+            Units::Block {
+                size: block,
+                suffix,
+            } => {
+                format!("{}{suffix}", size.div_ceil((*block).max(1)))
+            }
+        }
+    }
+}
+
+/// Writes `size` like `du -h` does: one decimal below 10, rounded up, and
+/// no unit below `base`
+fn human(size: u64, base: u64) -> String {
+    let base = base as f64;
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= base && unit < HUMAN.len() {
+        value /= base;
+        unit += 1;
+    }
+    if unit == 0 {
+        return size.to_string();
+    }
+
+    let mut number = match value < 10.0 {
+        true => (value * 10.0).ceil() / 10.0,
+        false => value.ceil(),
+    };
+    if number >= base && unit < HUMAN.len() {
+        number /= base;
+        unit += 1;
+    }
+    let suffix = match (base as u64, HUMAN[unit - 1]) {
+        (1000, "K") => "k",
+        (_, suffix) => suffix,
+    };
+    match number < 10.0 {
+        true => format!("{number:.1}{suffix}"),
+        false => format!("{number:.0}{suffix}"),
+    }
+}
+
+/// Leaves out of every root what an earlier root already counted, since du
+/// counts every file once however many arguments lead to it
+pub fn count_once(roots: Vec<FSEntity>) -> Vec<FSEntity> {
+    let mut counted: Vec<FSEntity> = vec![];
+    for mut root in roots {
+        if counted
+            .iter()
+            .any(|earlier| earlier.locate(root.path()).is_some())
+        {
+            continue;
+        }
+        for earlier in &counted {
+            if let Some(path) = root.locate(earlier.path()) {
+                root.remove(&path);
+            }
+        }
+        counted.push(root);
+    }
+    counted
+}
+
+/// Writes `SIZE<TAB>PATH` lines for the entries of every root in post-order,
+/// the way GNU `du` does, followed by a `total` line if asked for. Like du,
+/// roots that could not be read at all are left out, of the total too
+pub fn write_du(out: &mut impl Write, roots: &[&FSEntity], options: &DuOptions) -> io::Result<()> {
+    let roots = roots
+        .iter()
+        .filter(|root| {
+            !root
+                .errors
+                .iter()
+                .any(|error| error.op == ScanOp::Metadata && error.path == root.path)
+        })
+        .collect::<Vec<_>>();
+    for root in &roots {
+        write_entry(out, root, 0, options)?;
+    }
+    if options.total {
+        let total = roots.iter().map(|root| root.size).sum();
+        writeln!(out, "{}\ttotal", options.size(total))?;
+    }
+    Ok(())
+}
+
+fn write_entry(
+    out: &mut impl Write,
+    entity: &FSEntity,
+    level: u32,
+    options: &DuOptions,
+) -> io::Result<()> {
+    for child in entity.children() {
+        write_entry(out, child, level + 1, options)?;
+    }
+
+    // Like du, skipped mount points and names of inodes already counted are
+    // not listed, and a root is always
+    let listed = match entity.kind {
+        FSType::Folder(_) => true,
+        FSType::MountPoint => false,
+        _ if entity.linked => false,
+        _ => options.all || level == 0,
+    };
+    if listed && options.max_depth.is_none_or(|max| level <= max) {
+        write!(out, "{}\t", options.size(entity.size))?;
+        out.write_all(entity.path.as_os_str().as_bytes())?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::metadata;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::Scanner;
+
+    #[test]
+    fn human_rounds_up_like_du() {
+        let cases = [
+            (0, "0"),
+            (1023, "1023"),
+            (1024, "1.0K"),
+            (1025, "1.1K"),
+            (10 * 1024, "10K"),
+            (10 * 1024 + 1, "11K"),
+            (1023 * 1024 + 1, "1.0M"),
+            (1024 * 1024, "1.0M"),
+            (5 << 30, "5.0G"),
+        ];
+        for (size, expected) in cases {
+            assert_eq!(human(size, 1024), expected, "{size} bytes");
+        }
+    }
+
+    #[test]
+    fn human_si_writes_a_lower_case_k() {
+        let cases = [
+            (999, "999"),
+            (1000, "1.0k"),
+            (1001, "1.1k"),
+            (9999, "10k"),
+            (999_999, "1.0M"),
+            (1_000_001, "1.1M"),
+        ];
+        for (size, expected) in cases {
+            assert_eq!(human(size, 1000), expected, "{size} bytes");
+        }
+    }
+
+    #[test]
+    fn write_du_skips_names_already_counted() {
+        let linked = FSEntity {
+            linked: true,
+            ..FSEntity::fixture("t/b", 0, FSType::File)
+        };
+        let root = FSEntity::fixture(
+            "t",
+            4096,
+            FSType::Folder(vec![
+                FSEntity::fixture("t/a", 4096, FSType::File),
+                linked,
+                FSEntity::fixture("t/e", 0, FSType::File),
+                FSEntity::fixture("t/m", 0, FSType::MountPoint),
+            ]),
+        );
+        let options = DuOptions {
+            all: true,
+            units: Units::Bytes,
+            ..DuOptions::default()
+        };
+        let mut out = vec![];
+        write_du(&mut out, &[&root], &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "4096\tt/a\n0\tt/e\n8192\tt\n"
+        );
+    }
+
+    #[test]
+    fn write_du_leaves_out_unreadable_roots() {
+        let dir = TempDir::new();
+        let missing = async_std::task::block_on(Scanner::new().scan(dir.path().join("missing")));
+        let root = FSEntity::fixture("t", 10, FSType::File);
+        let options = DuOptions {
+            total: true,
+            units: Units::Bytes,
+            ..DuOptions::default()
+        };
+        let mut out = vec![];
+        write_du(&mut out, &[&missing.root, &root], &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "10\tt\n10\ttotal\n");
+    }
+
+    #[test]
+    fn blocks_round_up() {
+        let options = DuOptions::default();
+        assert_eq!(options.size(0), "0");
+        assert_eq!(options.size(1), "1");
+        assert_eq!(options.size(4096), "4");
+        assert_eq!(options.size(4097), "5");
+    }
+
+    #[test]
+    fn count_once_leaves_out_what_earlier_roots_counted() {
+        let dir = TempDir::new();
+        dir.file("a/f", &[0; 10]);
+        dir.file("g", &[0; 20]);
+        let scan = |path: PathBuf| async_std::task::block_on(Scanner::new().scan(path)).root;
+
+        let roots = count_once(vec![scan(dir.path().join("a")), scan(dir.path().into())]);
+        let sizes = roots
+            .iter()
+            .map(|root| root.apparent_size)
+            .collect::<Vec<_>>();
+        let own = |path: PathBuf| metadata(path).unwrap().len();
+        assert_eq!(
+            sizes,
+            [own(dir.path().join("a")) + 10, own(dir.path().into()) + 20]
+        );
+
+        let roots = count_once(vec![scan(dir.path().into()), scan(dir.path().join("a"))]);
+        assert_eq!(roots.len(), 1);
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::entity::FSEntity;
+use crate::error::{ScanError, ScanOp};
+use crate::format::SizeFormat;
+
+/// Bytes hashed from the start of every candidate before comparing them
+const PARTIAL: usize = 4096;
+
+/// Files with the same contents
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// Size of every file of the group
+    pub size: u64,
+    /// Every name of the contents, sorted
+    pub files: Vec<PathBuf>,
+    /// Bytes freed by keeping a single copy; names of the same inode share
+    /// their blocks, so they do not count
+    pub reclaimable: u64,
+}
+
+/// Groups of duplicate files, the most reclaimable first
+#[derive(Clone, Debug, Default)]
+pub struct Duplicates {
+    pub groups: Vec<DuplicateGroup>,
+    /// Files that could not be compared
+    pub errors: Vec<ScanError>,
+}
+
+impl Duplicates {
+    /// Bytes freed by keeping a single copy of every group
+    pub fn reclaimable(&self) -> u64 {
+        self.groups.iter().map(|group| group.reclaimable).sum()
+    }
+}
+
+/// A file that may have duplicates, with the inode it is a name of
+struct Candidate {
+    path: PathBuf,
+    inode: (u64, u64),
+}
+
+/// Finds the files below `roots` with the same contents. Files are grouped
+/// by size, then by a hash of their first bytes, and only the files still
+/// grouped are compared byte by byte, so most files are never read in full
+/// and a hash collision never makes a group. Empty files and files smaller
+/// than `min_size` are left out. With `ignore_hard_links` every inode is
+/// reported under a single name, so hard links alone never make a group
+///
+/// The tree should be scanned with [`HardLinks::All`](crate::HardLinks::All)
+/// for every name of an inode to be sized
+pub fn duplicates(roots: &[&FSEntity], min_size: u64, ignore_hard_links: bool) -> Duplicates {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut pending = roots.to_vec();
+    while let Some(entity) = pending.pop() {
+        pending.extend(entity.children());
+        if entity.is_file() && entity.apparent_size != 0 && entity.apparent_size >= min_size {
+            by_size
+                .entry(entity.apparent_size)
+                .or_default()
+                .push(entity.path.clone());
+        }
+    }
+
+    let mut found = Duplicates::default();
+    for (size, paths) in by_size {
+        if paths.len() < 2 {
+            continue;
+        }
+
+        let mut candidates = vec![];
+        for path in paths {
+            match fs::metadata(&path) {
+                Ok(meta) => candidates.push(Candidate {
+                    path,
+                    inode: (meta.dev(), meta.ino()),
+                }),
+                Err(err) => found
+                    .errors
+                    .push(ScanError::new(&path, ScanOp::Metadata, &err)),
+            }
+        }
+        if ignore_hard_links {
+            candidates.sort_by(|a, b| a.path.cmp(&b.path));
+            let mut seen = HashSet::new();
+            candidates.retain(|candidate| seen.insert(candidate.inode));
+        }
+
+        for hashed in split(candidates, &mut found.errors) {
+            for mut same in confirm(hashed, &mut found.errors) {
+                same.sort_by(|a, b| a.path.cmp(&b.path));
+                let mut inodes = same.iter().map(|file| file.inode).collect::<Vec<_>>();
+                inodes.sort();
+                inodes.dedup();
+                found.groups.push(DuplicateGroup {
+                    size,
+                    reclaimable: size * (inodes.len() as u64 - 1),
+                    files: same.into_iter().map(|file| file.path).collect(),
+                });
+            }
+        }
+    }
+
+    found.groups.sort_by(|a, b| {
+        b.reclaimable
+            .cmp(&a.reclaimable)
+            .then(b.size.cmp(&a.size))
+            .then(a.files.cmp(&b.files))
+    });
+    found.errors.sort_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+/// Splits `candidates` by the hash of their first bytes, keeping the groups
+/// of more than one file
+fn split(candidates: Vec<Candidate>, errors: &mut Vec<ScanError>) -> Vec<Vec<Candidate>> {
+    if candidates.len() < 2 {
+        return vec![];
+    }
+
+    let mut by_hash: HashMap<u64, Vec<Candidate>> = HashMap::new();
+    for candidate in candidates {
+        match hash(&candidate.path) {
+            Ok(hash) => by_hash.entry(hash).or_default().push(candidate),
+            Err(err) => errors.push(ScanError::new(&candidate.path, ScanOp::Read, &err)),
+        }
+    }
+    by_hash
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+/// Splits files with the same hash by comparing their contents, keeping the
+/// groups of more than one file. Names of the same inode are not read
+fn confirm(candidates: Vec<Candidate>, errors: &mut Vec<ScanError>) -> Vec<Vec<Candidate>> {
+    let mut groups: Vec<Vec<Candidate>> = vec![];
+    'candidates: for candidate in candidates {
+        for group in groups.iter_mut() {
+            let first = &group[0];
+            let same = match first.inode == candidate.inode {
+                true => Ok(true),
+                false => same_contents(&first.path, &candidate.path),
+            };
+            match same {
+                Ok(true) => {
+                    group.push(candidate);
+                    continue 'candidates;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    errors.push(ScanError::new(&candidate.path, ScanOp::Read, &err));
+                    continue 'candidates;
+                }
+            }
+        }
+        groups.push(vec![candidate]);
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Whether two files hold the same bytes
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut left, mut right) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let read = fill(&mut a, &mut left)?;
+        if fill(&mut b, &mut right)? != read || left[..read] != right[..read] {
+            return Ok(false);
+        }
+        if read == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Reads until `buffer` is full or the file ends, returning the bytes read
+fn fill(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buffer.len() {
+        match file.read(&mut buffer[total..]) {
+            Ok(0) => break,
+            Ok(read) => total += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+/// Hash of the first [`PARTIAL`] bytes of the file at `path`
+fn hash(path: &Path) -> io::Result<u64> {
+    let mut buffer = [0; PARTIAL];
+    let read = fill(&mut File::open(path)?, &mut buffer)?;
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&buffer[..read]);
+    Ok(hasher.finish())
+}
+
+/// Writes every group with its names, followed by the bytes all of them
+/// would free
+pub fn write_duplicates(
+    out: &mut impl Write,
+    duplicates: &Duplicates,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    for group in &duplicates.groups {
+        writeln!(
+            out,
+            "DUPLICATES\t[{} reclaimable]\t[{} files of {}]",
+            format.format(group.reclaimable),
+            group.files.len(),
+            format.format(group.size)
+        )?;
+        for path in &group.files {
+            writeln!(
+                out,
+                "FILE\t[{}]\t|_ {}",
+                format.format(group.size),
+                path.display()
+            )?;
+        }
+    }
+    writeln!(
+        out,
+        "Duplicates: {} groups, {} files, {} reclaimable",
+        duplicates.groups.len(),
+        duplicates
+            .groups
+            .iter()
+            .map(|group| group.files.len())
+            .sum::<usize>(),
+        format.format(duplicates.reclaimable())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task::block_on;
+
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::{HardLinks, Scanner};
+
+    fn find(dir: &TempDir, min_size: u64, ignore_hard_links: bool) -> Duplicates {
+        let scanner = Scanner::new().hard_links(HardLinks::All);
+        let root = block_on(scanner.scan(dir.path())).root;
+        duplicates(&[&root], min_size, ignore_hard_links)
+    }
+
+    fn names(dir: &TempDir, group: &DuplicateGroup) -> Vec<String> {
+        group
+            .files
+            .iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn hash_covers_the_first_bytes_only() {
+        let dir = TempDir::new();
+        let mut contents = vec![7; PARTIAL + 10];
+        let a = dir.file("a", &contents);
+        contents[PARTIAL] = 8;
+        let b = dir.file("b", &contents);
+        contents[0] = 8;
+        let c = dir.file("c", &contents);
+
+        assert_eq!(hash(&a).unwrap(), hash(&b).unwrap());
+        assert_ne!(hash(&a).unwrap(), hash(&c).unwrap());
+        assert!(!same_contents(&a, &b).unwrap());
+    }
+
+    #[test]
+    fn duplicates_group_files_with_the_same_contents() {
+        let dir = TempDir::new();
+        let mut contents = vec![1; PARTIAL * 2];
+        dir.file("a", &contents);
+        dir.file("sub/b", &contents);
+        contents[PARTIAL + 1] = 2;
+        dir.file("same_start", &contents);
+        dir.file("small/x", b"xyz");
+        dir.file("small/y", b"xyz");
+        dir.file("empty/e", b"");
+        dir.file("empty/f", b"");
+
+        let found = find(&dir, 0, false);
+        assert!(found.errors.is_empty());
+        let groups = found
+            .groups
+            .iter()
+            .map(|group| (names(&dir, group), group.reclaimable))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            [
+                (vec!["a".into(), "sub/b".into()], PARTIAL as u64 * 2),
+                (vec!["small/x".into(), "small/y".into()], 3),
+            ]
+        );
+        assert_eq!(found.reclaimable(), PARTIAL as u64 * 2 + 3);
+
+        assert_eq!(find(&dir, 4, false).groups.len(), 1);
+    }
+
+    #[test]
+    fn hard_links_free_nothing() {
+        let dir = TempDir::new();
+        let a = dir.file("a", b"contents");
+        std::fs::hard_link(&a, dir.path().join("b")).unwrap();
+        dir.file("c", b"contents");
+
+        let found = find(&dir, 0, false);
+        assert_eq!(found.groups.len(), 1);
+        assert_eq!(names(&dir, &found.groups[0]), ["a", "b", "c"]);
+        assert_eq!(found.groups[0].reclaimable, 8);
+
+        let found = find(&dir, 0, true);
+        assert_eq!(names(&dir, &found.groups[0]), ["a", "c"]);
+        assert_eq!(found.groups[0].reclaimable, 8);
+
+        std::fs::remove_file(dir.path().join("c")).unwrap();
+        assert!(find(&dir, 0, true).groups.is_empty());
+        let found = find(&dir, 0, false);
+        assert_eq!(found.groups[0].reclaimable, 0);
+    }
+}
@@ -0,0 +1,948 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_std::fs::{metadata, symlink_metadata, FileType};
+use async_std::task::spawn;
+use futures::future::{join_all, BoxFuture};
+use futures::{FutureExt, StreamExt};
+
+use crate::cache::{DirKey, EntryKind, Listed, Stat};
+use crate::error::{ScanError, ScanOp};
+use crate::filter::Ignores;
+use crate::scanner::{ScanContext, SizeMode, SortBy};
+
+/// Kind of a scanned entry
+#[derive(Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum FSType {
+    /// A directory together with its scanned children
+    Folder(Vec<FSEntity>),
+    /// A regular file
+    File,
+    /// A folder on another file system that was not descended into
+    MountPoint,
+    /// A symbolic link that was not followed
+    Symlink,
+    /// Any other special file, which is never descended into
+    Other(OtherKind),
+}
+
+/// Kind of a special file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OtherKind {
+    Socket,
+    Fifo,
+    BlockDevice,
+    CharDevice,
+    Unknown,
+}
+
+impl From<FileType> for OtherKind {
+    fn from(file_type: FileType) -> Self {
+        if file_type.is_socket() {
+            OtherKind::Socket
+        } else if file_type.is_fifo() {
+            OtherKind::Fifo
+        } else if file_type.is_block_device() {
+            OtherKind::BlockDevice
+        } else if file_type.is_char_device() {
+            OtherKind::CharDevice
+        } else {
+            OtherKind::Unknown
+        }
+    }
+}
+
+impl PartialOrd for FSType {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FSType {
+    fn cmp(&self, other: &Self) -> Ordering {
+        Ord::cmp(&self.rank(), &other.rank())
+    }
+}
+
+impl FSType {
+    /// Children of a folder, `None` for any other kind
+    pub fn children(&self) -> Option<&[FSEntity]> {
+        match self {
+            FSType::Folder(ref list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Mutable children of a folder, `None` for any other kind
+    pub fn children_mut(&mut self) -> Option<&mut Vec<FSEntity>> {
+        match self {
+            FSType::Folder(ref mut list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn is_folder(&self) -> bool {
+        matches!(self, FSType::Folder(_))
+    }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self, FSType::File)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        matches!(self, FSType::Symlink)
+    }
+
+    pub fn is_mount_point(&self) -> bool {
+        matches!(self, FSType::MountPoint)
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Folder(_) => 4,
+            Self::MountPoint => 3,
+            Self::File => 2,
+            Self::Symlink => 1,
+            Self::Other(_) => 0,
+        }
+    }
+
+    pub(crate) fn printable_description(&self) -> &'static str {
+        match self {
+            Self::Folder(_) => "FOLDER",
+            Self::File => "FILE",
+            Self::MountPoint => "MOUNT",
+            Self::Symlink => "SYMLINK",
+            Self::Other(OtherKind::Socket) => "SOCKET",
+            Self::Other(OtherKind::Fifo) => "FIFO",
+            Self::Other(OtherKind::BlockDevice) => "BLOCK",
+            Self::Other(OtherKind::CharDevice) => "CHAR",
+            Self::Other(OtherKind::Unknown) => "UNKNOWN",
+        }
+    }
+}
+
+impl Display for FSType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.printable_description())
+    }
+}
+
+/// User and group an entry belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Last modification and access of an entry, in seconds since the Unix epoch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Times {
+    pub modified: i64,
+    pub accessed: i64,
+}
+
+/// Which of the [`Times`] of an entry its age is measured by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeField {
+    #[default]
+    Modified,
+    Accessed,
+}
+
+impl Times {
+    pub fn get(&self, field: TimeField) -> i64 {
+        match field {
+            TimeField::Modified => self.modified,
+            TimeField::Accessed => self.accessed,
+        }
+    }
+}
+
+/// A scanned file system entry and, for folders, everything below it
+#[derive(Debug, Eq, PartialEq)]
+pub struct FSEntity {
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) apparent_size: u64,
+    pub(crate) disk_size: u64,
+    pub(crate) excluded_size: u64,
+    pub(crate) kind: FSType,
+    pub(crate) owner: Option<Owner>,
+    pub(crate) times: Option<Times>,
+    /// A name of a hard linked file whose inode was already counted under
+    /// another name, which is why it counts for nothing
+    pub(crate) linked: bool,
+    pub(crate) errors: Vec<ScanError>,
+}
+
+impl PartialOrd for FSEntity {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FSEntity {
+    fn cmp(&self, other: &Self) -> Ordering {
+        Ord::cmp(&self.kind, &other.kind)
+            .then(Ord::cmp(&self.size, &other.size))
+            .then(Ord::cmp(&self.path, &other.path))
+    }
+}
+
+impl FSEntity {
+    /// Path of the entry as it was reached from the scan root
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size in bytes, including everything below a folder, measured the way
+    /// the scanner was configured with [`Scanner::size_mode`](crate::Scanner::size_mode)
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Length of the contents in bytes, as reported by `ls -l`
+    pub fn apparent_size(&self) -> u64 {
+        self.apparent_size
+    }
+
+    /// Bytes allocated on disk, as reported by `du`
+    pub fn disk_size(&self) -> u64 {
+        self.disk_size
+    }
+
+    /// Bytes of the entries of a folder that were excluded from the tree;
+    /// only measured when [`Scanner::report_excluded`](crate::Scanner::report_excluded) is set
+    pub fn excluded_size(&self) -> u64 {
+        self.excluded_size
+    }
+
+    /// Failures met while measuring this entry; a folder only lists its own,
+    /// not those of its children
+    pub fn errors(&self) -> &[ScanError] {
+        &self.errors
+    }
+
+    /// Whether this entry or anything below it could not be fully measured
+    pub fn is_incomplete(&self) -> bool {
+        !self.errors.is_empty() || self.children().iter().any(FSEntity::is_incomplete)
+    }
+
+    /// Every failure met below this entry, this entry's own first
+    pub fn all_errors(&self) -> Box<dyn Iterator<Item = &ScanError> + '_> {
+        Box::new(
+            self.errors
+                .iter()
+                .chain(self.children().iter().flat_map(FSEntity::all_errors)),
+        )
+    }
+
+    /// Size in bytes measured in the given mode
+    pub fn size_in(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.apparent_size,
+            SizeMode::Disk => self.disk_size,
+        }
+    }
+
+    pub fn kind(&self) -> &FSType {
+        &self.kind
+    }
+
+    /// Owner of the entry itself, unknown when its metadata could not be read
+    pub fn owner(&self) -> Option<Owner> {
+        self.owner
+    }
+
+    /// Times of the entry itself, unknown when its metadata could not be read
+    pub fn times(&self) -> Option<Times> {
+        self.times
+    }
+
+    /// Latest time of the entries below a folder, or of the entry itself
+    /// when it is not a folder or nothing below it has a known time. The
+    /// times of a folder are left out otherwise, since listing it during
+    /// the scan is enough to change them
+    pub fn newest(&self, field: TimeField) -> Option<i64> {
+        self.children()
+            .iter()
+            .filter_map(|child| child.newest(field))
+            .max()
+            .or_else(|| self.times.map(|times| times.get(field)))
+    }
+
+    /// Number of entries below a folder, the folder itself excluded
+    pub fn count(&self) -> u64 {
+        self.children().iter().map(|child| 1 + child.count()).sum()
+    }
+
+    /// The entity at `path` in this tree, this one included
+    pub fn find(&self, path: &Path) -> Option<&FSEntity> {
+        if self.path == path {
+            return Some(self);
+        }
+        self.children()
+            .iter()
+            .find(|child| path.starts_with(&child.path))?
+            .find(path)
+    }
+
+    /// Path that `path` has in this tree however it was spelled, relative,
+    /// absolute or through symlinked folders, or `None` if it does not lead
+    /// below this entity. The tree may still have no entry at that path
+    pub fn locate(&self, path: &Path) -> Option<PathBuf> {
+        let canonical_root = self.path.canonicalize().ok()?;
+        let canonical = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .canonicalize()
+            .ok()?
+            .join(path.file_name()?);
+        let relative = canonical.strip_prefix(canonical_root).ok()?;
+        Some(self.path.join(relative))
+    }
+
+    /// Puts `entity` in place of the descendant with the same path, keeping
+    /// the sizes of every folder in between up to date. Returns the replaced
+    /// entity, or `None` if there was no such descendant
+    pub fn replace(&mut self, entity: FSEntity) -> Option<FSEntity> {
+        let path = entity.path.clone();
+        self.splice(&path, Some(entity))
+    }
+
+    /// Like [`FSEntity::replace`], but adds `entity` to its parent folder
+    /// when the tree has no entry at its path yet. Returns `false` if the
+    /// parent folder is not in the tree either
+    pub fn insert(&mut self, entity: FSEntity) -> bool {
+        if self.find(&entity.path).is_some() {
+            self.replace(entity);
+            return true;
+        }
+        self.add(entity).is_none()
+    }
+
+    /// Removes the descendant at `path`, subtracting its sizes from every
+    /// folder above it
+    pub fn remove(&mut self, path: &Path) -> Option<FSEntity> {
+        self.splice(path, None)
+    }
+
+    /// Sorts the children of every folder from this one down to `path`,
+    /// after an update below them
+    pub(crate) fn sort_along(&mut self, path: &Path, order: SortBy) {
+        let Some(list) = self.kind.children_mut() else {
+            return;
+        };
+        sort(list, order);
+        if let Some(child) = list.iter_mut().find(|child| path.starts_with(&child.path)) {
+            child.sort_along(path, order);
+        }
+    }
+
+    fn totals(&self) -> [u64; 3] {
+        [self.size, self.apparent_size, self.disk_size]
+    }
+
+    fn adjust_totals(&mut self, before: [u64; 3], after: [u64; 3]) {
+        for (total, (before, after)) in
+            [&mut self.size, &mut self.apparent_size, &mut self.disk_size]
+                .into_iter()
+                .zip(before.into_iter().zip(after))
+        {
+            *total = (*total + after).saturating_sub(before);
+        }
+    }
+
+    /// Adds `new` below its parent folder, handing it back if there is none
+    fn add(&mut self, new: FSEntity) -> Option<FSEntity> {
+        let path = self.path.clone();
+        let Some(list) = self.kind.children_mut() else {
+            return Some(new);
+        };
+
+        if new.path.parent() == Some(&path) {
+            let added = new.totals();
+            list.push(new);
+            self.adjust_totals([0; 3], added);
+            return None;
+        }
+
+        let Some(child) = list
+            .iter_mut()
+            .find(|child| new.path.starts_with(&child.path))
+        else {
+            return Some(new);
+        };
+        let before = child.totals();
+        let rest = child.add(new);
+        let after = child.totals();
+        self.adjust_totals(before, after);
+        rest
+    }
+
+    fn splice(&mut self, path: &Path, new: Option<FSEntity>) -> Option<FSEntity> {
+        let list = self.kind.children_mut()?;
+        let index = list
+            .iter()
+            .position(|child| path.starts_with(&child.path))?;
+        let before = list[index].totals();
+
+        let (old, after) = if list[index].path == path {
+            match new {
+                Some(new) => {
+                    let after = new.totals();
+                    (std::mem::replace(&mut list[index], new), after)
+                }
+                None => (list.remove(index), [0; 3]),
+            }
+        } else {
+            let old = list[index].splice(path, new)?;
+            (old, list[index].totals())
+        };
+
+        self.adjust_totals(before, after);
+        Some(old)
+    }
+
+    /// Children of a folder, empty for any other kind
+    pub fn children(&self) -> &[FSEntity] {
+        self.kind.children().unwrap_or_default()
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.kind.is_folder()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind.is_file()
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind.is_symlink()
+    }
+
+    /// An entry without children, sized as `measured` and described by its
+    /// metadata unless reading it failed
+    fn leaf(
+        path: PathBuf,
+        kind: FSType,
+        measured: io::Result<((u64, u64), Stat)>,
+        context: &ScanContext,
+    ) -> Self {
+        let (errors, (apparent_size, disk_size), stat) = match measured {
+            Ok((sizes, stat)) => (vec![], sizes, Some(stat)),
+            Err(err) => (
+                vec![ScanError::new(&path, ScanOp::Metadata, &err)],
+                (0, 0),
+                None,
+            ),
+        };
+        FSEntity {
+            size: context.config.size_mode.pick(apparent_size, disk_size),
+            apparent_size,
+            disk_size,
+            excluded_size: 0,
+            path,
+            kind,
+            owner: stat.map(|stat| stat.owner()),
+            times: stat.map(|stat| stat.times()),
+            linked: false,
+            errors,
+        }
+    }
+
+    /// A regular file, or the target of a followed link. `stat` is the
+    /// cached metadata of the file, filled in when it has to be read
+    pub(crate) async fn file(
+        name: impl Into<PathBuf>,
+        stat: &mut Option<Stat>,
+        context: &ScanContext,
+    ) -> Self {
+        let path = name.into();
+        let mut linked = false;
+        let measured = stat_of(&path, stat, true).await.map(|stat| {
+            let sizes = context.file_sizes(&stat);
+            linked = sizes.is_none();
+            (sizes.unwrap_or_default(), stat)
+        });
+        FSEntity {
+            linked,
+            ..FSEntity::leaf(path, FSType::File, measured, context)
+        }
+    }
+
+    /// The link itself, sized by its own metadata rather than its target
+    pub(crate) async fn symlink(
+        name: impl Into<PathBuf>,
+        stat: &mut Option<Stat>,
+        context: &ScanContext,
+    ) -> Self {
+        let path = name.into();
+        let measured = stat_of(&path, stat, false)
+            .await
+            .map(|stat| (stat.sizes(), stat));
+        FSEntity::leaf(path, FSType::Symlink, measured, context)
+    }
+
+    pub(crate) async fn other(
+        name: impl Into<PathBuf>,
+        kind: OtherKind,
+        stat: &mut Option<Stat>,
+        context: &ScanContext,
+    ) -> Self {
+        let path = name.into();
+        let measured = stat_of(&path, stat, false)
+            .await
+            .map(|stat| (stat.sizes(), stat));
+        FSEntity::leaf(path, FSType::Other(kind), measured, context)
+    }
+
+    /// A folder whose metadata could not be read, so it cannot be told apart
+    /// from the folders already visited; it is not descended into and counts
+    /// for nothing, with the failure recorded
+    pub(crate) fn unreadable(path: PathBuf, err: io::Error, context: &ScanContext) -> Self {
+        FSEntity::leaf(path, FSType::Folder(vec![]), Err(err), context)
+    }
+
+    /// A folder already counted through another path while following symlinks
+    fn visited(path: PathBuf) -> Self {
+        FSEntity {
+            path,
+            size: 0,
+            apparent_size: 0,
+            disk_size: 0,
+            excluded_size: 0,
+            kind: FSType::Folder(vec![]),
+            owner: None,
+            times: None,
+            linked: false,
+            errors: vec![],
+        }
+    }
+
+    /// Scans a folder; its disk size includes the blocks of the folder itself.
+    /// `parent_dev` and `ignores` are those of the folder it was found in
+    pub(crate) async fn folder(
+        name: impl Into<PathBuf>,
+        parent_dev: Option<u64>,
+        ignores: Ignores,
+        context: Arc<ScanContext>,
+    ) -> Self {
+        let path = name.into();
+        let meta = metadata(&path).await;
+        let dev = meta.as_ref().ok().map(|meta| meta.dev());
+
+        if parent_dev.is_some() && dev.is_some() && parent_dev != dev {
+            let skipped = context.config.one_file_system;
+            context.crossed_mount(&path, skipped);
+            if skipped {
+                return FSEntity {
+                    path,
+                    size: 0,
+                    apparent_size: 0,
+                    disk_size: 0,
+                    excluded_size: 0,
+                    kind: FSType::MountPoint,
+                    owner: None,
+                    times: None,
+                    linked: false,
+                    errors: vec![],
+                };
+            }
+        }
+
+        let ignores = context.filter.enter(&path, &ignores);
+        let ((apparent_size, disk_size), key, stat, errors) = match meta {
+            Ok(meta) => (
+                (meta.len(), meta.blocks() * 512),
+                Some(DirKey::from(&meta)),
+                Some(Stat::from(&meta)),
+                vec![],
+            ),
+            Err(err) => (
+                (0, 0),
+                None,
+                None,
+                vec![ScanError::new(&path, ScanOp::Metadata, &err)],
+            ),
+        };
+        let mut entity = FSEntity {
+            path,
+            size: 0,
+            apparent_size,
+            disk_size,
+            excluded_size: 0,
+            kind: FSType::Folder(vec![]),
+            owner: stat.map(|stat| stat.owner()),
+            times: stat.map(|stat| stat.times()),
+            linked: false,
+            errors,
+        };
+        entity.calculate_size(dev, key, ignores, context).await;
+        entity
+    }
+
+    /// Every byte below an excluded entry, including what its own filters left out
+    fn total_excluded(&self) -> u64 {
+        self.size
+            + self.excluded_size
+            + self
+                .children()
+                .iter()
+                .map(|child| child.total_excluded() - child.size)
+                .sum::<u64>()
+    }
+
+    /// Entries of the folder, from the cache when it did not change since
+    /// the previous scan. `None` when the folder cannot be read; the listing
+    /// is incomplete, and must not be cached, when `complete` is `false`
+    async fn list(
+        &mut self,
+        key: Option<DirKey>,
+        context: &ScanContext,
+        complete: &mut bool,
+    ) -> Option<Vec<Listed>> {
+        if let (Some(cache), Some(key)) = (&context.cache, key) {
+            if let Some(entries) = cache.lookup(&self.path, key) {
+                return Some(entries);
+            }
+        }
+
+        let (mut dir, _permit) = match context.open_dir(&self.path).await {
+            Ok(open) => open,
+            Err(err) => {
+                self.errors
+                    .push(ScanError::new(&self.path, ScanOp::ReadDir, &err));
+                return None;
+            }
+        };
+
+        let mut entries = vec![];
+        while let Some(entry) = dir.next().await {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    self.errors
+                        .push(ScanError::new(&self.path, ScanOp::ReadEntry, &err));
+                    *complete = false;
+                    continue;
+                }
+            };
+            match entry.file_type().await {
+                Ok(file_type) => entries.push(Listed {
+                    name: entry.file_name(),
+                    kind: file_type.into(),
+                    stat: None,
+                }),
+                Err(err) => {
+                    let path: PathBuf = entry.path().into();
+                    self.errors
+                        .push(ScanError::new(&path, ScanOp::FileType, &err));
+                    *complete = false;
+                }
+            }
+        }
+        Some(entries)
+    }
+
+    fn calculate_size(
+        &mut self,
+        dev: Option<u64>,
+        key: Option<DirKey>,
+        ignores: Ignores,
+        context: Arc<ScanContext>,
+    ) -> BoxFuture<'_, u64> {
+        async move {
+            let mut tasks = vec![];
+            let mut excluded = vec![];
+
+            // The folder is closed again before its children open theirs
+            let mut complete = true;
+            let Some(mut entries) = self.list(key, &context, &mut complete).await else {
+                self.size = context
+                    .config
+                    .size_mode
+                    .pick(self.apparent_size, self.disk_size);
+                return self.size;
+            };
+
+            let Some(list) = self.kind.children_mut() else {
+                return 0;
+            };
+
+            for entry in entries.iter_mut() {
+                let path = self.path.join(&entry.name);
+                let is_dir = entry.kind == EntryKind::Dir;
+
+                if context.filter.excludes(&path, is_dir, &ignores) {
+                    if !context.config.report_excluded {
+                        continue;
+                    }
+                    if is_dir {
+                        let (context, ignores) = (context.excluded(), ignores.clone());
+                        excluded.push(spawn(async move {
+                            FSEntity::folder(path, dev, ignores, context).await
+                        }));
+                    } else if let Ok(stat) = stat_of(&path, &mut entry.stat, false).await {
+                        let (apparent, disk) = stat.sizes();
+                        self.excluded_size += context.config.size_mode.pick(apparent, disk);
+                    }
+                    continue;
+                }
+
+                match entry.kind {
+                    EntryKind::File => {
+                        list.push(FSEntity::file(path, &mut entry.stat, &context).await)
+                    }
+                    EntryKind::Dir => {
+                        if context.config.follow_symlinks {
+                            match context.first_visit(&path).await {
+                                Ok(true) => {}
+                                Ok(false) => {
+                                    list.push(FSEntity::visited(path));
+                                    continue;
+                                }
+                                Err(err) => {
+                                    list.push(FSEntity::unreadable(path, err, &context));
+                                    continue;
+                                }
+                            }
+                        }
+                        let (context, ignores) = (context.clone(), ignores.clone());
+                        tasks.push(spawn(async move {
+                            FSEntity::folder(path, dev, ignores, context).await
+                        }));
+                    }
+                    EntryKind::Symlink => {
+                        let target = match context.config.follow_symlinks {
+                            true => metadata(&path).await.ok(),
+                            false => None,
+                        };
+                        match target {
+                            Some(target) if target.is_file() => {
+                                list.push(FSEntity::file(path, &mut None, &context).await)
+                            }
+                            Some(target) if target.is_dir() => {
+                                match context.first_visit(&path).await {
+                                    Ok(true) => {
+                                        let (context, ignores) = (context.clone(), ignores.clone());
+                                        tasks.push(spawn(async move {
+                                            FSEntity::folder(path, dev, ignores, context).await
+                                        }));
+                                    }
+                                    Ok(false) => list.push(
+                                        FSEntity::symlink(path, &mut entry.stat, &context).await,
+                                    ),
+                                    Err(err) => {
+                                        list.push(FSEntity::unreadable(path, err, &context))
+                                    }
+                                }
+                            }
+                            _ => {
+                                list.push(FSEntity::symlink(path, &mut entry.stat, &context).await)
+                            }
+                        }
+                    }
+                    EntryKind::Other(kind) => {
+                        list.push(FSEntity::other(path, kind, &mut entry.stat, &context).await)
+                    }
+                }
+            }
+
+            if let (Some(cache), Some(key), true) = (&context.cache, key, complete) {
+                if cache.validating() {
+                    for entry in entries
+                        .iter_mut()
+                        .filter(|entry| entry.kind != EntryKind::Dir)
+                    {
+                        let path = self.path.join(&entry.name);
+                        let _ =
+                            stat_of(&path, &mut entry.stat, entry.kind == EntryKind::File).await;
+                    }
+                }
+                cache.store(&self.path, key, entries);
+            }
+
+            let mut results = join_all(tasks).await;
+            list.append(&mut results);
+            for entity in join_all(excluded).await {
+                self.excluded_size += entity.total_excluded();
+            }
+            sort(list, context.config.sort);
+            self.apparent_size += list.iter().map(|x| x.apparent_size).sum::<u64>();
+            self.disk_size += list.iter().map(|x| x.disk_size).sum::<u64>();
+            self.size = context
+                .config
+                .size_mode
+                .pick(self.apparent_size, self.disk_size);
+            self.size
+        }
+        .boxed()
+    }
+}
+
+fn sort(list: &mut [FSEntity], order: SortBy) {
+    match order {
+        SortBy::Size => list.sort_by(|a, b| b.cmp(a)),
+        SortBy::Name => list.sort_by(|a, b| a.path.cmp(&b.path)),
+    }
+}
+
+/// Metadata of `path`, read from the disk unless `cached` already holds it.
+/// `follow` sizes the target of a link rather than the link itself
+async fn stat_of(path: &Path, cached: &mut Option<Stat>, follow: bool) -> io::Result<Stat> {
+    if let Some(stat) = cached {
+        return Ok(*stat);
+    }
+    let meta = match follow {
+        true => metadata(path).await?,
+        false => symlink_metadata(path).await?,
+    };
+    let stat = Stat::from(&meta);
+    *cached = Some(stat);
+    Ok(stat)
+}
+
+#[cfg(test)]
+impl FSEntity {
+    /// An entry of `kind` owning `size` bytes, apparent and on disk alike,
+    /// without metadata; a folder adds the sizes of its children
+    pub(crate) fn fixture(path: &str, size: u64, kind: FSType) -> Self {
+        let size = size
+            + kind
+                .children()
+                .unwrap_or_default()
+                .iter()
+                .map(|child| child.size)
+                .sum::<u64>();
+        FSEntity {
+            path: path.into(),
+            size,
+            apparent_size: size,
+            disk_size: size,
+            excluded_size: 0,
+            kind,
+            owner: None,
+            times: None,
+            linked: false,
+            errors: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn locate_follows_other_spellings() {
+        let dir = TempDir::new();
+        dir.file("a/b", b"");
+        let root = FSEntity::fixture(dir.path().to_str().unwrap(), 0, FSType::Folder(vec![]));
+
+        let spelled = dir.path().join("a/../a/b");
+        assert_eq!(root.locate(&spelled), Some(dir.path().join("a/b")));
+        assert_eq!(
+            root.locate(&dir.path().join("new")),
+            Some(dir.path().join("new"))
+        );
+        assert_eq!(root.locate(Path::new("/")), None);
+    }
+
+    fn tree() -> FSEntity {
+        FSEntity::fixture(
+            "t",
+            0,
+            FSType::Folder(vec![
+                FSEntity::fixture(
+                    "t/a",
+                    0,
+                    FSType::Folder(vec![
+                        FSEntity::fixture("t/a/f", 10, FSType::File),
+                        FSEntity::fixture("t/a/g", 5, FSType::File),
+                    ]),
+                ),
+                FSEntity::fixture("t/h", 3, FSType::File),
+            ]),
+        )
+    }
+
+    fn sizes(root: &FSEntity, paths: &[&str]) -> Vec<[u64; 3]> {
+        paths
+            .iter()
+            .map(|path| root.find(Path::new(path)).unwrap().totals())
+            .collect()
+    }
+
+    #[test]
+    fn remove_updates_every_folder_above() {
+        let mut root = tree();
+
+        let removed = root.remove(Path::new("t/a/f")).unwrap();
+        assert_eq!(removed.path, Path::new("t/a/f"));
+        assert_eq!(sizes(&root, &["t", "t/a"]), [[8; 3], [5; 3]]);
+        assert!(root.find(Path::new("t/a/f")).is_none());
+
+        assert!(root.remove(Path::new("t/a/f")).is_none());
+        assert!(root.remove(Path::new("t")).is_none());
+        assert_eq!(sizes(&root, &["t"]), [[8; 3]]);
+    }
+
+    #[test]
+    fn replace_only_replaces_existing_entries() {
+        let mut root = tree();
+
+        let old = root.replace(FSEntity::fixture("t/a/g", 50, FSType::File));
+        assert_eq!(old.map(|old| old.size), Some(5));
+        assert_eq!(sizes(&root, &["t", "t/a"]), [[63; 3], [60; 3]]);
+
+        assert!(root
+            .replace(FSEntity::fixture("t/a/new", 1, FSType::File))
+            .is_none());
+        assert_eq!(sizes(&root, &["t"]), [[63; 3]]);
+    }
+
+    #[test]
+    fn insert_adds_below_a_known_parent() {
+        let mut root = tree();
+
+        assert!(root.insert(FSEntity::fixture("t/a/new", 7, FSType::File)));
+        assert_eq!(
+            sizes(&root, &["t", "t/a", "t/a/new"]),
+            [[25; 3], [22; 3], [7; 3]]
+        );
+
+        assert!(root.insert(FSEntity::fixture("t/h", 1, FSType::File)));
+        assert_eq!(sizes(&root, &["t"]), [[23; 3]]);
+
+        assert!(!root.insert(FSEntity::fixture("t/x/y", 1, FSType::File)));
+        assert!(!root.insert(FSEntity::fixture("t/h/y", 1, FSType::File)));
+        assert_eq!(sizes(&root, &["t"]), [[23; 3]]);
+    }
+
+    #[test]
+    fn sort_along_orders_the_updated_folders() {
+        let mut root = tree();
+        root.insert(FSEntity::fixture("t/a/big", 100, FSType::File));
+        root.sort_along(Path::new("t/a/big"), SortBy::Size);
+
+        let names = |path: &str| {
+            root.find(Path::new(path))
+                .unwrap()
+                .children()
+                .iter()
+                .map(|child| child.path.to_str().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("t"), ["t/a", "t/h"]);
+        assert_eq!(names("t/a"), ["t/a/big", "t/a/f", "t/a/g"]);
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Serializer};
+
+/// Operation of the scan that failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanOp {
+    /// Opening a folder to list it
+    ReadDir,
+    /// Reading the next entry of an open folder
+    ReadEntry,
+    /// Getting the type of an entry
+    FileType,
+    /// Getting the size and identity of an entry
+    Metadata,
+    /// Reading the contents of a file
+    Read,
+}
+
+impl Display for ScanOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ScanOp::ReadDir => "Reading folder",
+            ScanOp::ReadEntry => "Reading entry of",
+            ScanOp::FileType => "Getting file type of",
+            ScanOp::Metadata => "Getting metadata of",
+            ScanOp::Read => "Reading",
+        })
+    }
+}
+
+/// An I/O failure that left part of the tree unmeasured
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub op: ScanOp,
+    #[serde(rename = "kind", serialize_with = "serialize_kind")]
+    pub io_kind: io::ErrorKind,
+}
+
+fn serialize_kind<S: Serializer>(kind: &io::ErrorKind, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(kind)
+}
+
+impl ScanError {
+    pub(crate) fn new(path: &Path, op: ScanOp, err: &io::Error) -> Self {
+        ScanError {
+            path: path.to_owned(),
+            op,
+            io_kind: err.kind(),
+        }
+    }
+}
+
+impl Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: {}", self.op, self.path.display(), self.io_kind)
+    }
+}
+
+impl std::error::Error for ScanError {}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+/// Names of the ignore files honored in every folder, the last one winning
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Decides which entries of a scan are left out of the tree
+#[derive(Debug)]
+pub(crate) struct Filter {
+    root: PathBuf,
+    exclude: GlobSet,
+    include: Option<GlobSet>,
+    gitignore: bool,
+}
+
+/// Ignore files of a folder and of all the folders above it
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Ignores(Option<Arc<IgnoreLayer>>);
+
+#[derive(Debug)]
+struct IgnoreLayer {
+    matcher: Gitignore,
+    parent: Ignores,
+}
+
+fn build(globs: &[Glob]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob.clone());
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+impl Filter {
+    pub fn new(root: &Path, exclude: &[Glob], include: &[Glob], gitignore: bool) -> Self {
+        Filter {
+            root: root.to_owned(),
+            exclude: build(exclude),
+            include: (!include.is_empty()).then(|| build(include)),
+            gitignore,
+        }
+    }
+
+    /// Adds the ignore files found in `dir`, when they are honored
+    pub fn enter(&self, dir: &Path, parent: &Ignores) -> Ignores {
+        if !self.gitignore {
+            return parent.clone();
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        for name in IGNORE_FILES {
+            let file = dir.join(name);
+            if file.is_file() {
+                builder.add(file);
+            }
+        }
+
+        match builder.build() {
+            Ok(matcher) if !matcher.is_empty() => Ignores(Some(Arc::new(IgnoreLayer {
+                matcher,
+                parent: parent.clone(),
+            }))),
+            _ => parent.clone(),
+        }
+    }
+
+    /// Whether `path` is left out of the tree. Exclusions apply to every
+    /// entry, matching either its name or its path relative to the root;
+    /// inclusions only restrict what non-folder entries are counted
+    pub fn excludes(&self, path: &Path, is_dir: bool, ignores: &Ignores) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let name = path.file_name().map(Path::new).unwrap_or(relative);
+
+        if self.exclude.is_match(name) || self.exclude.is_match(relative) {
+            return true;
+        }
+
+        if let Some(include) = &self.include {
+            if !is_dir && !include.is_match(name) && !include.is_match(relative) {
+                return true;
+            }
+        }
+
+        ignores.ignored(path, is_dir)
+    }
+}
+
+impl Ignores {
+    /// The closest ignore file with an opinion on `path` decides
+    fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut layer = &self.0;
+        while let Some(current) = layer {
+            match current.matcher.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => layer = &current.parent.0,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn globs(patterns: &[&str]) -> Vec<Glob> {
+        patterns
+            .iter()
+            .map(|pattern| Glob::new(pattern).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn excludes_match_names_and_relative_paths() {
+        let root = Path::new("/r");
+        let filter = Filter::new(root, &globs(&["*.tmp", "a/b"]), &[], false);
+        let none = Ignores::default();
+
+        assert!(filter.excludes(Path::new("/r/x/y.tmp"), false, &none));
+        assert!(filter.excludes(Path::new("/r/a/b"), true, &none));
+        assert!(!filter.excludes(Path::new("/r/c/a/b"), true, &none));
+        assert!(!filter.excludes(Path::new("/r/y.txt"), false, &none));
+    }
+
+    #[test]
+    fn includes_only_restrict_files() {
+        let filter = Filter::new(Path::new("/r"), &[], &globs(&["*.rs"]), false);
+        let none = Ignores::default();
+
+        assert!(!filter.excludes(Path::new("/r/src"), true, &none));
+        assert!(!filter.excludes(Path::new("/r/src/main.rs"), false, &none));
+        assert!(filter.excludes(Path::new("/r/README.md"), false, &none));
+    }
+
+    #[test]
+    fn closest_ignore_file_decides() {
+        let dir = TempDir::new();
+        dir.file(".gitignore", b"*.log\nbuild/\n");
+        dir.file("sub/.ignore", b"!keep.log\n");
+        let filter = Filter::new(dir.path(), &[], &[], true);
+
+        let root = filter.enter(dir.path(), &Ignores::default());
+        let sub = filter.enter(&dir.path().join("sub"), &root);
+
+        assert!(filter.excludes(&dir.path().join("a.log"), false, &root));
+        assert!(filter.excludes(&dir.path().join("build"), true, &root));
+        assert!(!filter.excludes(&dir.path().join("build"), false, &root));
+        assert!(filter.excludes(&dir.path().join("sub/a.log"), false, &sub));
+        assert!(!filter.excludes(&dir.path().join("sub/keep.log"), false, &sub));
+        assert!(filter.excludes(&dir.path().join("keep.log"), false, &root));
+
+        let ignored = Filter::new(dir.path(), &[], &[], false);
+        let root = ignored.enter(dir.path(), &Ignores::default());
+        assert!(!ignored.excludes(&dir.path().join("a.log"), false, &root));
+    }
+}
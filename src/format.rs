@@ -0,0 +1,483 @@
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Marks where a shortened path was cut
+const ELLIPSIS: &str = "...";
+
+/// Units sizes are written in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Units {
+    /// Powers of 1024 with binary prefixes: KiB, MiB, GiB up to EiB
+    #[default]
+    Iec,
+    /// Powers of 1000 with decimal prefixes: kB, MB, GB up to EB
+    Si,
+    /// The exact number of bytes
+    Bytes,
+    /// Always a number of blocks of `size` bytes followed by `suffix`, like
+    /// the `--block-size` of `du`
+    Block { size: u64, suffix: String },
+}
+
+/// How reports write sizes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeFormat {
+    pub units: Units,
+    /// Digits after the decimal point of scaled sizes
+    pub precision: usize,
+}
+
+impl Default for SizeFormat {
+    /// IEC units with two decimals, e.g. `1.50 MiB`
+    fn default() -> Self {
+        SizeFormat::DEFAULT
+    }
+}
+
+const IEC: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const SI: &[&str] = &["kB", "MB", "GB", "TB", "PB", "EB"];
+
+impl SizeFormat {
+    const DEFAULT: SizeFormat = SizeFormat {
+        units: Units::Iec,
+        precision: 2,
+    };
+
+    /// Writes `size` bytes in these units. Blocks are rounded up when no
+    /// decimals are asked for, so that a non empty file never shows as 0
+    pub fn format(&self, size: u64) -> String {
+        let precision = self.precision;
+        match &self.units {
+            Units::Iec => scaled(size, 1024, IEC, precision),
+            Units::Si => scaled(size, 1000, SI, precision),
+            Units::Bytes => size.to_string(),
+            Units::Block {
+                size: block,
+                suffix,
+            } => match precision {
+                0 => format!("{}{suffix}", size.div_ceil((*block).max(1))),
+                _ => format!(
+                    "{:.precision$}{suffix}",
+                    size as f64 / (*block).max(1) as f64
+                ),
+            },
+        }
+    }
+}
+
+fn scaled(size: u64, base: u64, units: &[&str], precision: usize) -> String {
+    let mut unit = 0;
+    let mut divisor = 1;
+    while unit < units.len() && size / divisor >= base {
+        divisor *= base;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{size} B"),
+        unit => format!(
+            "{:.precision$} {}",
+            size as f64 / divisor as f64,
+            units[unit - 1]
+        ),
+    }
+}
+
+/// Formats a byte count in the default [`SizeFormat`], with a 1024 based
+/// unit, e.g. `1.50 MiB`
+pub fn format_size(size: u64) -> String {
+    SizeFormat::DEFAULT.format(size)
+}
+
+/// Writes `path` in full, escaping what a terminal cannot show as is: bytes
+/// that are not UTF-8 as `\xNN`, control characters as `\n`, `\t` or
+/// `\u{NN}` and backslashes as `\\`, so that no two paths look the same
+pub fn escape_path(path: &Path) -> String {
+    units(path).into_iter().map(|(unit, _)| unit).collect()
+}
+
+/// Escapes `path` like [`escape_path`] and shortens it to `width` columns
+/// by keeping its start and its end around `...`. Wide characters count for
+/// two columns and grapheme clusters are never split. A width too small for
+/// `...` only leaves as many dots
+pub fn format_path(path: &Path, width: usize) -> String {
+    let units = units(path);
+    if units.iter().map(|(_, width)| width).sum::<usize>() <= width {
+        return units.into_iter().map(|(unit, _)| unit).collect();
+    }
+    if width <= ELLIPSIS.len() {
+        return ELLIPSIS[..width].to_owned();
+    }
+
+    let budget = width.saturating_sub(ELLIPSIS.len());
+    let start = fitting(units.iter(), budget - budget / 2);
+    let end = fitting(units[start..].iter().rev(), budget / 2);
+
+    let mut out = String::new();
+    out.extend(units[..start].iter().map(|(unit, _)| unit.as_str()));
+    out.push_str(ELLIPSIS);
+    out.extend(
+        units[units.len() - end..]
+            .iter()
+            .map(|(unit, _)| unit.as_str()),
+    );
+    out
+}
+
+/// Number of `units` that fit in `width` columns, taken in order
+fn fitting<'a>(units: impl Iterator<Item = &'a (String, usize)>, mut width: usize) -> usize {
+    let mut count = 0;
+    for (_, taken) in units {
+        if *taken > width {
+            break;
+        }
+        width -= taken;
+        count += 1;
+    }
+    count
+}
+
+/// The escaped path cut into the pieces a shortened path may not split,
+/// with the columns each of them takes
+fn units(path: &Path) -> Vec<(String, usize)> {
+    let mut units = vec![];
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        for grapheme in chunk.valid().graphemes(true) {
+            let unit = match grapheme.chars().any(|c| c.is_control() || c == '\\') {
+                true => grapheme.chars().map(escape_char).collect(),
+                false => grapheme.to_owned(),
+            };
+            let width = unit.width();
+            units.push((unit, width));
+        }
+        for byte in chunk.invalid() {
+            units.push((format!("\\x{byte:02x}"), 4));
+        }
+    }
+    units
+}
+
+fn escape_char(c: char) -> String {
+    match c {
+        '\\' => "\\\\".to_owned(),
+        '\n' => "\\n".to_owned(),
+        '\t' => "\\t".to_owned(),
+        '\r' => "\\r".to_owned(),
+        c if c.is_control() => format!("\\u{{{:x}}}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+/// Parses a human size such as `4096`, `10K`, `1.5M` or `2GiB` into bytes.
+/// Like in [`parse_block_size`], `K`, `M`... and `KiB`, `MiB`... are powers
+/// of 1024 and `KB`, `MB`... powers of 1000
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{input}`"))?;
+
+    let invalid = || format!("invalid size unit in `{input}`");
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        unit => {
+            let (prefix, rest) = unit.split_at(unit.chars().next().map_or(0, char::len_utf8));
+            let power = "KMGTPE".find(prefix).ok_or_else(invalid)? as i32 + 1;
+            let base: f64 = match rest {
+                "" | "IB" => 1024.0,
+                "B" => 1000.0,
+                _ => return Err(invalid()),
+            };
+            base.powi(power)
+        }
+    };
+
+    Ok((number * multiplier) as u64)
+}
+
+/// Size below which the entries of a folder are merged into one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threshold {
+    /// Entries smaller than this many bytes
+    Bytes(u64),
+    /// Entries smaller than this share of their folder, in percent
+    Percent(f64),
+}
+
+impl Threshold {
+    /// Whether an entry of `size` bytes in a folder of `parent_size` is
+    /// below the threshold
+    pub fn below(&self, size: u64, parent_size: u64) -> bool {
+        match *self {
+            Threshold::Bytes(bytes) => size < bytes,
+            Threshold::Percent(percent) => (size as f64) < parent_size as f64 * percent / 100.0,
+        }
+    }
+}
+
+/// Parses a threshold given as a size, such as `10K`, or as a percentage of
+/// the folder, such as `0.5%`
+pub fn parse_threshold(input: &str) -> Result<Threshold, String> {
+    match input.trim().strip_suffix('%') {
+        Some(percent) => match percent.trim().parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Threshold::Percent(percent)),
+            _ => Err(format!("invalid percentage `{input}`")),
+        },
+        None => parse_size(input).map(Threshold::Bytes),
+    }
+}
+
+/// Parses an age such as `90s`, `30m`, `12h`, `180d`, `2w` or `1y` into a
+/// duration; a bare number counts days
+pub fn parse_age(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid age `{input}`"))?;
+
+    let seconds: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" | "min" => 60,
+        "h" => 60 * 60,
+        "" | "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Err(format!("invalid age unit in `{input}`")),
+    };
+
+    Ok(Duration::from_secs_f64(number * seconds as f64))
+}
+
+/// Parses a block size the way `du --block-size` does: `K`, `M`, `G`, `T`,
+/// `P`, `E` or `KiB`, `MiB`... for powers of 1024, `KB`, `MB`... for powers
+/// of 1000, optionally after a count such as `4K`, or a bare number of
+/// bytes. Sizes are suffixed with the unit only when no count is given
+pub fn parse_block_size(input: &str) -> Result<Units, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (count, unit) = input.split_at(split);
+    let invalid = || format!("invalid block size `{input}`");
+    if input.is_empty() {
+        return Err(invalid());
+    }
+
+    let power = match unit.chars().next().map(|c| c.to_ascii_uppercase()) {
+        None => 0,
+        Some(prefix) => "KMGTPE".find(prefix).ok_or_else(invalid)? as u32 + 1,
+    };
+    let base: u64 = match unit.get(1..).unwrap_or_default() {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return Err(invalid()),
+    };
+    let size = match count {
+        "" => Some(1),
+        count => count.parse::<u64>().ok(),
+    }
+    .and_then(|count| base.checked_pow(power)?.checked_mul(count))
+    .filter(|&size| size != 0)
+    .ok_or_else(invalid)?;
+
+    // du writes the prefix in upper case, but for the k of kB
+    let suffix = match (count.is_empty(), power, base) {
+        (false, _, _) | (_, 0, _) => String::new(),
+        (_, 1, 1000) => "kB".to_owned(),
+        _ => unit[..1].to_ascii_uppercase() + &unit[1..],
+    };
+    Ok(Units::Block { size, suffix })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    fn shortened(path: &str, width: usize) -> String {
+        format_path(Path::new(path), width)
+    }
+
+    #[test]
+    fn format_path_keeps_paths_that_fit() {
+        assert_eq!(shortened("src/main.rs", 11), "src/main.rs");
+        assert_eq!(shortened("src/main.rs", 80), "src/main.rs");
+    }
+
+    #[test]
+    fn format_path_cuts_the_middle() {
+        assert_eq!(
+            shortened("/home/user/projects/weights", 13),
+            "/home...ights"
+        );
+    }
+
+    #[test]
+    fn format_path_never_splits_multi_byte_characters() {
+        let path = "/données/éléphant/été.txt";
+        for width in 0..path.len() {
+            let short = shortened(path, width);
+            assert!(short.width() <= width, "{short:?} is wider than {width}");
+        }
+        assert_eq!(shortened(path, 12), "/donn....txt");
+    }
+
+    #[test]
+    fn format_path_counts_wide_characters_twice() {
+        assert_eq!(shortened("数据/文件文件.txt", 10), "数据...txt");
+        assert_eq!(shortened("数据/文件文件.txt", 9), "数...txt");
+        assert_eq!(shortened("文件文件", 7), "文...件");
+        assert_eq!(shortened("文件文件", 6), "文...");
+    }
+
+    #[test]
+    fn format_path_never_splits_graphemes() {
+        let path = "cafe\u{301}/cafe\u{301}";
+        for width in 0..10 {
+            let short = shortened(path, width);
+            assert!(short.width() <= width, "{short:?} is wider than {width}");
+            assert!(!short.contains("...\u{301}"), "{short:?} splits a grapheme");
+        }
+    }
+
+    #[test]
+    fn format_path_escapes_invalid_utf8_whole() {
+        let path = Path::new(OsStr::from_bytes(b"a\xffb"));
+        assert_eq!(escape_path(path), "a\\xffb");
+        assert_eq!(format_path(path, 6), "a\\xffb");
+        assert_eq!(format_path(path, 5), "a...b");
+        assert_eq!(format_path(path, 4), "a...");
+    }
+
+    #[test]
+    fn format_path_escapes_control_characters() {
+        assert_eq!(escape_path(Path::new("a\nb\tc\\d")), "a\\nb\\tc\\\\d");
+        assert_eq!(escape_path(Path::new("\u{1b}[31m")), "\\u{1b}[31m");
+    }
+
+    #[test]
+    fn format_path_below_the_ellipsis_width() {
+        assert_eq!(shortened("abcdef", 0), "");
+        assert_eq!(shortened("abcdef", 1), ".");
+        assert_eq!(shortened("abcdef", 2), "..");
+        assert_eq!(shortened("abcdef", 3), "...");
+        assert_eq!(shortened("abcdef", 4), "a...");
+    }
+
+    fn block(size: u64, suffix: &str) -> Units {
+        Units::Block {
+            size,
+            suffix: suffix.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("1.5M"), Ok(1024 * 1024 * 3 / 2));
+        assert_eq!(parse_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size(" 3 kb "), Ok(3000));
+        assert_eq!(parse_size("1t"), Ok(1 << 40));
+        assert_eq!(parse_size("12B"), Ok(12));
+    }
+
+    #[test]
+    fn parse_size_agrees_with_block_sizes() {
+        for unit in [
+            "", "K", "KB", "KiB", "M", "MB", "MiB", "G", "GB", "T", "TB", "PB",
+        ] {
+            let input = format!("3{unit}");
+            let Ok(Units::Block { size, .. }) = parse_block_size(&input) else {
+                panic!("{input} is not a block size");
+            };
+            assert_eq!(parse_size(&input), Ok(size), "{input}");
+        }
+    }
+
+    #[test]
+    fn parse_size_rejects_garbage() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("1.2.3M").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn parse_block_size_like_du() {
+        assert_eq!(parse_block_size("K"), Ok(block(1024, "K")));
+        assert_eq!(parse_block_size("k"), Ok(block(1024, "K")));
+        assert_eq!(parse_block_size("KiB"), Ok(block(1024, "KiB")));
+        assert_eq!(parse_block_size("KB"), Ok(block(1000, "kB")));
+        assert_eq!(parse_block_size("MB"), Ok(block(1000 * 1000, "MB")));
+        assert_eq!(parse_block_size("G"), Ok(block(1 << 30, "G")));
+        assert_eq!(parse_block_size("E"), Ok(block(1 << 60, "E")));
+    }
+
+    #[test]
+    fn parse_block_size_with_a_count_has_no_suffix() {
+        assert_eq!(parse_block_size("4K"), Ok(block(4096, "")));
+        assert_eq!(parse_block_size("2MB"), Ok(block(2 * 1000 * 1000, "")));
+        assert_eq!(parse_block_size("512"), Ok(block(512, "")));
+        assert_eq!(parse_block_size("1"), Ok(block(1, "")));
+    }
+
+    #[test]
+    fn parse_block_size_rejects_garbage() {
+        assert!(parse_block_size("").is_err());
+        assert!(parse_block_size("0").is_err());
+        assert!(parse_block_size("0K").is_err());
+        assert!(parse_block_size("Q").is_err());
+        assert!(parse_block_size("KX").is_err());
+        assert!(parse_block_size("1.5K").is_err());
+        assert!(parse_block_size("16E").is_err());
+    }
+
+    #[test]
+    fn parse_threshold_sizes_and_percentages() {
+        assert_eq!(parse_threshold("10K"), Ok(Threshold::Bytes(10 * 1024)));
+        assert_eq!(parse_threshold("0.5%"), Ok(Threshold::Percent(0.5)));
+        assert_eq!(parse_threshold(" 5 % "), Ok(Threshold::Percent(5.0)));
+        assert_eq!(parse_threshold("100%"), Ok(Threshold::Percent(100.0)));
+        assert!(parse_threshold("101%").is_err());
+        assert!(parse_threshold("-1%").is_err());
+        assert!(parse_threshold("x%").is_err());
+        assert!(parse_threshold("%").is_err());
+        assert!(parse_threshold("10X").is_err());
+    }
+
+    #[test]
+    fn threshold_below() {
+        assert!(Threshold::Bytes(100).below(99, 1000));
+        assert!(!Threshold::Bytes(100).below(100, 1000));
+        assert!(Threshold::Percent(5.0).below(49, 1000));
+        assert!(!Threshold::Percent(5.0).below(50, 1000));
+        assert!(!Threshold::Percent(5.0).below(0, 0));
+    }
+
+    #[test]
+    fn size_format_units() {
+        let format = |units, precision| SizeFormat { units, precision };
+        assert_eq!(format(Units::Iec, 2).format(1536), "1.50 KiB");
+        assert_eq!(format(Units::Iec, 2).format(1023), "1023 B");
+        assert_eq!(format(Units::Si, 1).format(1500), "1.5 kB");
+        assert_eq!(format(Units::Bytes, 2).format(1536), "1536");
+        assert_eq!(format(block(1024, "K"), 0).format(1), "1K");
+        assert_eq!(format(block(1024, ""), 1).format(1536), "1.5");
+    }
+}
@@ -0,0 +1,279 @@
+use std::io::{self, Write};
+
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Serialize, Serializer};
+
+use crate::entity::{FSEntity, FSType, OtherKind};
+use crate::error::ScanError;
+use crate::format::escape_path;
+use crate::report::{ratio, PrintOptions};
+
+impl Serialize for FSType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            FSType::Folder(_) => "folder",
+            FSType::File => "file",
+            FSType::MountPoint => "mount_point",
+            FSType::Symlink => "symlink",
+            FSType::Other(OtherKind::Socket) => "socket",
+            FSType::Other(OtherKind::Fifo) => "fifo",
+            FSType::Other(OtherKind::BlockDevice) => "block_device",
+            FSType::Other(OtherKind::CharDevice) => "char_device",
+            FSType::Other(OtherKind::Unknown) => "unknown",
+        })
+    }
+}
+
+/// Serializes the complete tree below this entity
+impl Serialize for FSEntity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        JsonTree {
+            entity: self,
+            parent_size: self.size,
+            level: 0,
+            options: &PrintOptions::default(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Nested view of an entity honoring the printing options
+struct JsonTree<'a> {
+    entity: &'a FSEntity,
+    parent_size: u64,
+    level: u32,
+    options: &'a PrintOptions,
+}
+
+impl Serialize for JsonTree<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entity = self.entity;
+        let is_folder = matches!(entity.kind, FSType::Folder(_));
+
+        let has_excluded = entity.excluded_size != 0;
+        let has_errors = !entity.errors.is_empty();
+
+        let mut state = serializer.serialize_struct(
+            "FSEntity",
+            6 + 2 * usize::from(entity.owner.is_some())
+                + 2 * usize::from(entity.times.is_some())
+                + usize::from(is_folder)
+                + usize::from(has_excluded)
+                + usize::from(has_errors),
+        )?;
+        state.serialize_field("path", &escape_path(&entity.path))?;
+        state.serialize_field("size", &entity.size)?;
+        state.serialize_field("apparent_size", &entity.apparent_size)?;
+        state.serialize_field("disk_size", &entity.disk_size)?;
+        state.serialize_field("kind", &entity.kind)?;
+        state.serialize_field("percentage", &ratio(entity.size, self.parent_size))?;
+        if let Some(owner) = entity.owner {
+            state.serialize_field("uid", &owner.uid)?;
+            state.serialize_field("gid", &owner.gid)?;
+        }
+        if let Some(times) = entity.times {
+            state.serialize_field("mtime", &times.modified)?;
+            state.serialize_field("atime", &times.accessed)?;
+        }
+        if has_excluded {
+            state.serialize_field("excluded_size", &entity.excluded_size)?;
+        }
+        if has_errors {
+            state.serialize_field("errors", &entity.errors)?;
+        }
+        if is_folder {
+            state.serialize_field("children", &JsonChildren(self))?;
+        }
+        state.end()
+    }
+}
+
+struct JsonChildren<'a>(&'a JsonTree<'a>);
+
+impl Serialize for JsonChildren<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let JsonTree {
+            entity,
+            level,
+            options,
+            ..
+        } = *self.0;
+
+        let children = if options.descends(level) {
+            options.visible(entity.children())
+        } else {
+            vec![]
+        };
+
+        let mut seq = serializer.serialize_seq(Some(children.len()))?;
+        for child in children {
+            seq.serialize_element(&JsonTree {
+                entity: child,
+                parent_size: entity.size,
+                level: level + 1,
+                options,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+/// Flat record used by the NDJSON output, one per entry, with the same
+/// fields as the nested tree apart from `children`
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    path: String,
+    size: u64,
+    apparent_size: u64,
+    disk_size: u64,
+    kind: &'a FSType,
+    percentage: f64,
+    depth: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    atime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    excluded_size: Option<u64>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [ScanError],
+}
+
+/// Writes a single tree as an object, or several trees as an array
+pub fn write_json(
+    out: &mut impl Write,
+    roots: &[&FSEntity],
+    options: &PrintOptions,
+) -> io::Result<()> {
+    let trees = roots
+        .iter()
+        .map(|root| JsonTree {
+            entity: root,
+            parent_size: root.size,
+            level: 0,
+            options,
+        })
+        .collect::<Vec<_>>();
+
+    write_one_or_many(out, &trees)
+}
+
+/// Writes a single report as a JSON object, or several as an array, as
+/// every JSON output does for one root or several
+pub(crate) fn write_one_or_many(
+    out: &mut impl Write,
+    reports: &[impl Serialize],
+) -> io::Result<()> {
+    match reports {
+        [report] => serde_json::to_writer_pretty(&mut *out, report)?,
+        reports => serde_json::to_writer_pretty(&mut *out, reports)?,
+    }
+    writeln!(out)
+}
+
+/// Streams one flat record per entry of `root`, one JSON object per line
+pub fn write_ndjson(
+    out: &mut impl Write,
+    root: &FSEntity,
+    options: &PrintOptions,
+) -> io::Result<()> {
+    write_record(out, root, root.size, 0)?;
+    write_ndjson_children(out, root, 0, options)
+}
+
+fn write_ndjson_children(
+    out: &mut impl Write,
+    parent: &FSEntity,
+    level: u32,
+    options: &PrintOptions,
+) -> io::Result<()> {
+    if !options.descends(level) {
+        return Ok(());
+    }
+
+    for entity in options.visible(parent.children()) {
+        write_record(out, entity, parent.size, level + 1)?;
+        if let FSType::Folder(_) = entity.kind {
+            write_ndjson_children(out, entity, level + 1, options)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_record(
+    out: &mut impl Write,
+    entity: &FSEntity,
+    parent_size: u64,
+    depth: u32,
+) -> io::Result<()> {
+    let record = JsonRecord {
+        path: escape_path(&entity.path),
+        size: entity.size,
+        apparent_size: entity.apparent_size,
+        disk_size: entity.disk_size,
+        kind: &entity.kind,
+        percentage: ratio(entity.size, parent_size),
+        depth,
+        uid: entity.owner.map(|owner| owner.uid),
+        gid: entity.owner.map(|owner| owner.gid),
+        mtime: entity.times.map(|times| times.modified),
+        atime: entity.times.map(|times| times.accessed),
+        excluded_size: (entity.excluded_size != 0).then_some(entity.excluded_size),
+        errors: &entity.errors,
+    };
+    serde_json::to_writer(&mut *out, &record)?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    fn tree() -> FSEntity {
+        let odd = FSEntity {
+            path: OsStr::from_bytes(b"t/\xff").into(),
+            ..FSEntity::fixture("", 10, FSType::File)
+        };
+        FSEntity {
+            excluded_size: 5,
+            ..FSEntity::fixture("t", 0, FSType::Folder(vec![odd]))
+        }
+    }
+
+    #[test]
+    fn write_json_escapes_paths() {
+        let mut out = vec![];
+        write_json(&mut out, &[&tree()], &PrintOptions::default()).unwrap();
+        let json: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["children"][0]["path"], "t/\\xff");
+    }
+
+    #[test]
+    fn ndjson_records_match_the_tree_fields() {
+        let root = tree();
+        let mut out = vec![];
+        write_json(&mut out, &[&root], &PrintOptions::default()).unwrap();
+        let mut json: Value = serde_json::from_slice(&out).unwrap();
+        json.as_object_mut().unwrap().remove("children");
+
+        let mut out = vec![];
+        write_ndjson(&mut out, &root, &PrintOptions::default()).unwrap();
+        let lines = out.split(|&byte| byte == b'\n').collect::<Vec<_>>();
+        let mut record: Value = serde_json::from_slice(lines[0]).unwrap();
+        record.as_object_mut().unwrap().remove("depth");
+
+        assert_eq!(record, json);
+        assert_eq!(record["excluded_size"], 5);
+        let child: Value = serde_json::from_slice(lines[1]).unwrap();
+        assert_eq!(child["path"], "t/\\xff");
+    }
+}
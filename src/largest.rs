@@ -0,0 +1,280 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::entity::FSEntity;
+use crate::format::{escape_path, SizeFormat};
+use crate::json::write_one_or_many;
+use crate::report::ratio;
+
+/// The largest files and folders of a tree, largest first
+#[derive(Clone, Debug, Default)]
+pub struct Largest<'a> {
+    pub files: Vec<&'a FSEntity>,
+    /// Folders with their own size: the bytes of the folder itself and of
+    /// the entries directly inside it, not counting the folders below it
+    pub folders: Vec<(&'a FSEntity, u64)>,
+}
+
+/// Keeps the `n` largest of the values it is offered, holding no more than
+/// `n` of them at any time
+struct Bounded<'a> {
+    n: usize,
+    heap: BinaryHeap<Reverse<(u64, &'a FSEntity)>>,
+}
+
+impl<'a> Bounded<'a> {
+    fn new(n: usize) -> Self {
+        Bounded {
+            n,
+            heap: BinaryHeap::with_capacity(n + 1),
+        }
+    }
+
+    fn offer(&mut self, size: u64, entity: &'a FSEntity) {
+        if self.n == 0 {
+            return;
+        }
+        if self.heap.len() == self.n {
+            match self.heap.peek() {
+                Some(Reverse((smallest, _))) if *smallest >= size => return,
+                _ => {
+                    self.heap.pop();
+                }
+            }
+        }
+        self.heap.push(Reverse((size, entity)));
+    }
+
+    fn into_sorted(self) -> Vec<(&'a FSEntity, u64)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((size, entity))| (entity, size))
+            .collect()
+    }
+}
+
+/// Size of a folder without the folders below it
+pub fn own_size(folder: &FSEntity) -> u64 {
+    let below = folder
+        .children()
+        .iter()
+        .filter(|child| child.is_folder())
+        .map(|child| child.size)
+        .sum::<u64>();
+    folder.size.saturating_sub(below)
+}
+
+/// Every entry of `root` with the bytes it holds itself, none of its
+/// children's, so that summing them counts every byte of the tree once and
+/// a folder only for its own blocks. Folders holding no bytes of their own
+/// are left out, files never are
+pub(crate) fn own_bytes(root: &FSEntity) -> impl Iterator<Item = (&FSEntity, u64)> {
+    let mut pending = vec![root];
+    std::iter::from_fn(move || loop {
+        let entity = pending.pop()?;
+        pending.extend(entity.children());
+        let below = entity
+            .children()
+            .iter()
+            .map(|child| child.size)
+            .sum::<u64>();
+        let own = entity.size.saturating_sub(below);
+        if own != 0 || entity.is_file() {
+            return Some((entity, own));
+        }
+    })
+}
+
+/// Walks `root` once and keeps the `n` largest files and the `n` folders
+/// with the largest own size
+pub fn largest(root: &FSEntity, n: usize) -> Largest<'_> {
+    let mut files = Bounded::new(n);
+    let mut folders = Bounded::new(n);
+
+    let mut pending = vec![root];
+    while let Some(entity) = pending.pop() {
+        if entity.is_folder() {
+            folders.offer(own_size(entity), entity);
+            pending.extend(entity.children());
+        } else if entity.is_file() {
+            files.offer(entity.size, entity);
+        }
+    }
+
+    Largest {
+        files: files
+            .into_sorted()
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect(),
+        folders: folders.into_sorted(),
+    }
+}
+
+/// Writes the `n` largest files and folders of `root` with their full paths
+/// and their share of the total
+pub fn write_largest(
+    out: &mut impl Write,
+    root: &FSEntity,
+    n: usize,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    let largest = largest(root, n);
+    writeln!(
+        out,
+        "{}\t[{}]",
+        root.path.display(),
+        format.format(root.size)
+    )?;
+
+    writeln!(out, "Largest files:")?;
+    for file in &largest.files {
+        write_line(out, file.size, root.size, file, format)?;
+    }
+    writeln!(out, "Largest folders by own size:")?;
+    for (folder, size) in &largest.folders {
+        write_line(out, *size, root.size, folder, format)?;
+    }
+    Ok(())
+}
+
+fn write_line(
+    out: &mut impl Write,
+    size: u64,
+    total: u64,
+    entity: &FSEntity,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{typ}\t[{size} = {ratio:.2}%]\t{path}",
+        typ = entity.kind,
+        size = format.format(size),
+        ratio = ratio(size, total),
+        path = entity.path.display(),
+    )
+}
+
+#[derive(Serialize)]
+struct JsonLargest {
+    path: String,
+    files: Vec<JsonRanked>,
+    folders: Vec<JsonRanked>,
+}
+
+#[derive(Serialize)]
+struct JsonRanked {
+    path: String,
+    size: u64,
+    percentage: f64,
+}
+
+fn ranked(entity: &FSEntity, size: u64, total: u64) -> JsonRanked {
+    JsonRanked {
+        path: escape_path(&entity.path),
+        size,
+        percentage: ratio(size, total),
+    }
+}
+
+/// Writes the `n` largest files and folders of every root as a JSON object,
+/// or an array of them for several roots
+pub fn write_largest_json(out: &mut impl Write, roots: &[&FSEntity], n: usize) -> io::Result<()> {
+    let reports = roots
+        .iter()
+        .map(|root| {
+            let largest = largest(root, n);
+            JsonLargest {
+                path: escape_path(&root.path),
+                files: largest
+                    .files
+                    .iter()
+                    .map(|file| ranked(file, file.size, root.size))
+                    .collect(),
+                folders: largest
+                    .folders
+                    .iter()
+                    .map(|(folder, size)| ranked(folder, *size, root.size))
+                    .collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    write_one_or_many(out, &reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::FSType;
+
+    fn tree() -> FSEntity {
+        FSEntity::fixture(
+            "t",
+            1,
+            FSType::Folder(vec![
+                FSEntity::fixture(
+                    "t/a",
+                    2,
+                    FSType::Folder(vec![
+                        FSEntity::fixture("t/a/f", 30, FSType::File),
+                        FSEntity::fixture("t/a/g", 5, FSType::File),
+                    ]),
+                ),
+                FSEntity::fixture("t/h", 20, FSType::File),
+                FSEntity::fixture("t/l", 40, FSType::Symlink),
+            ]),
+        )
+    }
+
+    #[test]
+    fn bounded_keeps_the_largest() {
+        let entity = FSEntity::fixture("f", 0, FSType::File);
+        let mut bounded = Bounded::new(3);
+        for size in [5, 1, 9, 3, 7, 9, 2] {
+            bounded.offer(size, &entity);
+            assert!(bounded.heap.len() <= 3);
+        }
+        let sizes = bounded.into_sorted().into_iter().map(|(_, size)| size);
+        assert_eq!(sizes.collect::<Vec<_>>(), [9, 9, 7]);
+
+        let mut none = Bounded::new(0);
+        none.offer(1, &entity);
+        assert!(none.into_sorted().is_empty());
+    }
+
+    #[test]
+    fn own_size_leaves_out_subfolders() {
+        let root = tree();
+        assert_eq!(root.size, 98);
+        assert_eq!(own_size(&root), 61);
+        assert_eq!(own_size(&root.children()[0]), 37);
+    }
+
+    #[test]
+    fn own_bytes_counts_every_byte_once() {
+        let root = tree();
+        let own = own_bytes(&root)
+            .map(|(entity, size)| (entity.path.to_str().unwrap(), size))
+            .collect::<Vec<_>>();
+        assert_eq!(own.iter().map(|(_, size)| size).sum::<u64>(), root.size);
+        assert!(own.contains(&("t", 1)) && own.contains(&("t/a", 2)));
+    }
+
+    #[test]
+    fn largest_ranks_files_and_folders() {
+        let root = tree();
+        let largest = largest(&root, 2);
+        let files = largest.files.iter().map(|file| file.path.to_str().unwrap());
+        assert_eq!(files.collect::<Vec<_>>(), ["t/a/f", "t/h"]);
+        let folders = largest
+            .folders
+            .iter()
+            .map(|(folder, size)| (folder.path.to_str().unwrap(), *size));
+        assert_eq!(folders.collect::<Vec<_>>(), [("t", 61), ("t/a", 37)]);
+    }
+}
@@ -0,0 +1,55 @@
+//! Disk/Directory space usage scanner
+//!
+//! A [`Scanner`] walks a directory concurrently and returns an [`FSEntity`]
+//! tree whose folders carry the total size of everything below them. The tree
+//! can be printed with [`write_text`] or exported with [`write_json`] and
+//! [`write_ndjson`]; every entity is also `serde::Serialize`. Trees saved with
+//! [`write_snapshot`] can be read back and compared with [`write_diff`].
+
+mod actions;
+mod age;
+mod boxes;
+mod cache;
+mod diff;
+mod du;
+mod duplicates;
+mod entity;
+mod error;
+mod filter;
+mod format;
+mod json;
+mod largest;
+mod mounts;
+mod owners;
+mod report;
+mod scanner;
+mod snapshot;
+#[cfg(test)]
+mod testing;
+mod types;
+mod watch;
+
+pub use actions::{write_plan, Action, Applied, Plan};
+pub use age::{ages, unix_time, write_ages, write_ages_json, Ages};
+pub use cache::default_cache_dir;
+pub use diff::{diff, write_diff, Change, Delta};
+pub use du::{count_once, write_du, DuOptions};
+pub use duplicates::{duplicates, write_duplicates, DuplicateGroup, Duplicates};
+pub use entity::{FSEntity, FSType, OtherKind, Owner, TimeField, Times};
+pub use error::{ScanError, ScanOp};
+pub use format::{
+    escape_path, format_path, format_size, parse_age, parse_block_size, parse_size,
+    parse_threshold, SizeFormat, Threshold, Units,
+};
+pub use globset::Error as GlobError;
+pub use json::{write_json, write_ndjson};
+pub use largest::{largest, own_size, write_largest, write_largest_json, Largest};
+pub use mounts::{write_mounts, Mount};
+pub use owners::{ownership, write_owners, write_owners_json, Names, Ownership, Usage};
+pub use report::{write_errors, write_summary, write_text, PrintOptions, TreeStyle};
+pub use scanner::{default_jobs, HardLinks, Scan, ScanStats, Scanner, SizeMode, SortBy};
+pub use snapshot::{is_snapshot, read_snapshot, write_snapshot};
+pub use types::{
+    breakdown, sniff_type, write_breakdown, write_breakdown_json, Breakdown, Categories, TypeStats,
+};
+pub use watch::{Changes, Watch};
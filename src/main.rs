@@ -1,195 +1,453 @@
-use core::panic;
-use std::cmp::Ordering;
-use std::fmt::Display;
-
-use async_std::fs::{metadata, read_dir};
-use async_std::path::{Path, PathBuf};
-use async_std::task::spawn;
-use futures::future::{join_all, BoxFuture};
-use futures::{FutureExt, StreamExt};
-
-#[derive(Eq, PartialEq)]
-enum FSType {
-    Folder(Vec<FSEntity>),
-    File,
-}
+use std::fs::File;
+use std::io::{stderr, stdin, stdout, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use async_std::task::{sleep, spawn_blocking};
+use clap::Parser;
+
+use weights::{
+    Action, Changes, FSEntity, HardLinks, Plan, PrintOptions, Scan, Scanner, SizeFormat, Watch,
+};
 
-impl PartialOrd for FSType {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+use cli::{Args, Command, DuArgs, Format};
+
+mod cli;
+mod tui;
+
+#[async_std::main]
+async fn main() {
+    if cli::du_requested() {
+        du(DuArgs::parse()).await;
     }
-}
 
-impl Ord for FSType {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match (self, other) {
-            (FSType::File, FSType::Folder(_)) => Ordering::Less,
-            (FSType::Folder(_), FSType::File) => Ordering::Greater,
-            (_, _) => Ordering::Equal,
+    let args = Args::parse();
+
+    let options = PrintOptions {
+        max_depth: args.max_depth,
+        top: args.top,
+        min_size: args.min_size.unwrap_or(0),
+        older_than: args.older_than.map(|age| SystemTime::now() - age),
+        time: args.time.into(),
+        path_width: args.path_width(),
+        style: args.tree_style(),
+        collapse: args.collapse,
+        size_format: args.size_format(),
+    };
+
+    let scanner = match args.scanner() {
+        Ok(scanner) => scanner,
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(Command::Diff { old, new }) = &args.command {
+        let (old, new) = match (scanner.load(old).await, scanner.load(new).await) {
+            (Ok(old_tree), Ok(new_tree)) => (old_tree, new_tree),
+            (Err(err), _) => {
+                eprintln!("ERROR: Reading {}: {err}", old.display());
+                std::process::exit(2);
+            }
+            (_, Err(err)) => {
+                eprintln!("ERROR: Reading {}: {err}", new.display());
+                std::process::exit(2);
+            }
+        };
+
+        let mut out = BufWriter::new(stdout().lock());
+        if let Err(err) =
+            weights::write_diff(&mut out, &old, &new, &options).and_then(|_| out.flush())
+        {
+            eprintln!("ERROR: Writing output: {err}");
+            std::process::exit(1);
         }
+
+        let mut err = stderr().lock();
+        for root in [&old, &new] {
+            let _ = weights::write_errors(&mut err, root, args.quiet_errors);
+        }
+        if old.is_incomplete() || new.is_incomplete() {
+            std::process::exit(1);
+        }
+        return;
     }
-}
 
-impl FSType {
-    fn list(&self) -> &Vec<FSEntity> {
-        match self {
-            FSType::Folder(ref list) => list,
-            _ => panic!("Invalid FSType"),
+    if let Some(Command::Duplicates {
+        paths,
+        ignore_hard_links,
+    }) = &args.command
+    {
+        // Every name of an inode has to be sized to be compared
+        let scanner = scanner.clone().hard_links(HardLinks::All);
+        let mut roots = vec![];
+        for root in paths {
+            roots.push(scanner.scan(root).await.root);
         }
+        let found = weights::duplicates(
+            &roots.iter().collect::<Vec<_>>(),
+            options.min_size,
+            *ignore_hard_links,
+        );
+
+        let mut out = BufWriter::new(stdout().lock());
+        if let Err(err) = weights::write_duplicates(&mut out, &found, &options.size_format)
+            .and_then(|_| out.flush())
+        {
+            eprintln!("ERROR: Writing output: {err}");
+            std::process::exit(1);
+        }
+
+        let mut err = stderr().lock();
+        for root in &roots {
+            let _ = weights::write_errors(&mut err, root, args.quiet_errors);
+        }
+        for error in &found.errors {
+            if !args.quiet_errors {
+                let _ = writeln!(err, "ERROR: {error}");
+            }
+        }
+        if roots.iter().any(FSEntity::is_incomplete) || !found.errors.is_empty() {
+            std::process::exit(1);
+        }
+        return;
     }
 
-    fn list_mut(&mut self) -> &mut Vec<FSEntity> {
-        match self {
-            FSType::Folder(ref mut list) => list,
-            _ => panic!("Invalid FSType"),
+    if let Some(path) = &args.watch {
+        let interval = Duration::from_secs(args.interval.max(1));
+        // A live tree is updated as the disk changes, which the cache cannot tell
+        watch(path, scanner.cache(None), &options, interval).await;
+    }
+
+    let report = args.largest.is_some() || args.by_type || args.by_owner || args.by_age;
+    if report && args.format == Format::Ndjson {
+        eprintln!("ERROR: --largest, --by-type, --by-owner and --by-age write text or json");
+        std::process::exit(2);
+    }
+
+    let categories = match args.categories() {
+        Ok(categories) => categories,
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            std::process::exit(2);
         }
+    };
+
+    if args.interactive && args.paths.len() != 1 {
+        eprintln!("ERROR: --interactive browses a single root");
+        std::process::exit(2);
     }
 
-    fn printable_description(&self) -> &'static str {
-        match self {
-            Self::Folder(_) => "FOLDER",
-            Self::File => "FILE",
+    if args.save.is_some() && args.paths.len() != 1 {
+        eprintln!("ERROR: --save stores a single root");
+        std::process::exit(2);
+    }
+
+    let mut scans = vec![];
+    for root in &args.paths {
+        scans.push(scanner.scan(root).await);
+    }
+
+    let actions = [(Action::Delete, &args.delete), (Action::Trash, &args.trash)];
+    let mut failed = false;
+    for (action, paths) in actions.into_iter().filter(|(_, paths)| !paths.is_empty()) {
+        failed |= !apply(
+            action,
+            paths,
+            &mut scans,
+            args.dry_run,
+            &options.size_format,
+        )
+        .await;
+    }
+
+    if let Some(file) = &args.save {
+        if let Err(err) = save(file, &scans[0].root) {
+            eprintln!("ERROR: Saving {}: {err}", file.display());
+            std::process::exit(1);
         }
     }
-}
 
-impl Display for FSType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.printable_description())
+    if args.interactive {
+        let scan = scans.remove(0);
+        // A rescan is asked for to see the disk as it is now, not as cached
+        if let Err(err) = tui::run(
+            scan.root,
+            scanner.cache(None),
+            args.size_mode(),
+            options.size_format.clone(),
+            args.dry_run,
+        )
+        .await
+        {
+            eprintln!("ERROR: Interactive mode: {err}");
+            std::process::exit(1);
+        }
+        return;
     }
-}
 
-#[derive(Eq, PartialEq)]
-struct FSEntity {
-    path: PathBuf,
-    size: u64,
-    kind: FSType,
-}
+    let mut out = BufWriter::new(stdout().lock());
+    let roots = scans.iter().map(|scan| &scan.root).collect::<Vec<_>>();
+    let breakdowns = match args.by_type {
+        true => roots
+            .iter()
+            .map(|root| weights::breakdown(root, &categories, args.sniff))
+            .collect(),
+        false => vec![],
+    };
+    let names = match args.by_owner {
+        true => weights::Names::load(),
+        false => weights::Names::default(),
+    };
+    let ages = match args.by_age {
+        true => roots
+            .iter()
+            .map(|root| weights::ages(root, options.time, SystemTime::now()))
+            .collect(),
+        false => vec![],
+    };
+    let result = match (args.format, args.largest) {
+        (Format::Text, _) if args.by_age => roots.iter().zip(&ages).try_for_each(|(root, ages)| {
+            weights::write_ages(&mut out, root, ages, &options.size_format)
+        }),
+        (Format::Json, _) if args.by_age => {
+            let reports = roots.iter().copied().zip(&ages).collect::<Vec<_>>();
+            weights::write_ages_json(&mut out, &reports)
+        }
+        (Format::Text, _) if args.by_owner => roots.iter().try_for_each(|root| {
+            weights::write_owners(&mut out, root, &names, &options.size_format)
+        }),
+        (Format::Json, _) if args.by_owner => weights::write_owners_json(&mut out, &roots, &names),
+        (Format::Text, _) if args.by_type => {
+            roots
+                .iter()
+                .zip(&breakdowns)
+                .try_for_each(|(root, breakdown)| {
+                    weights::write_breakdown(&mut out, root, breakdown, &options.size_format)
+                })
+        }
+        (Format::Json, _) if args.by_type => {
+            let reports = roots.iter().copied().zip(&breakdowns).collect::<Vec<_>>();
+            weights::write_breakdown_json(&mut out, &reports)
+        }
+        (Format::Text, Some(n)) => roots
+            .iter()
+            .try_for_each(|root| weights::write_largest(&mut out, root, n, &options.size_format)),
+        (Format::Json, Some(n)) => weights::write_largest_json(&mut out, &roots, n),
+        (Format::Text, None) => scans.iter().try_for_each(|scan| {
+            weights::write_text(&mut out, &scan.root, &options)?;
+            weights::write_summary(&mut out, &scan.stats, &options.size_format)?;
+            if args.list_mounts {
+                weights::write_mounts(&mut out, &scan.stats.mounts)?;
+            }
+            Ok(())
+        }),
+        (Format::Json, None) => weights::write_json(&mut out, &roots, &options),
+        (Format::Ndjson, _) => scans
+            .iter()
+            .try_for_each(|scan| weights::write_ndjson(&mut out, &scan.root, &options)),
+    };
 
-impl PartialOrd for FSEntity {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+    if let Err(err) = result.and_then(|_| out.flush()) {
+        eprintln!("ERROR: Writing output: {err}");
+        std::process::exit(1);
     }
-}
 
-impl Ord for FSEntity {
-    fn cmp(&self, other: &Self) -> Ordering {
-        Ord::cmp(&self.kind, &other.kind)
-            .then(Ord::cmp(&self.size, &other.size))
-            .then(Ord::cmp(&self.path, &other.path))
+    let mut err = stderr().lock();
+    for scan in &scans {
+        let _ = weights::write_errors(&mut err, &scan.root, args.quiet_errors);
+    }
+    if failed || scans.iter().any(|scan| scan.root.is_incomplete()) {
+        std::process::exit(1);
     }
 }
 
-fn format_size(size: u64) -> String {
-    if size / (1024 * 1024 * 1024) != 0 {
-        format!("{:.2} GB", size as f64 / (1024 * 1024 * 1024) as f64)
-    } else if size / (1024 * 1024) != 0 {
-        format!("{:.2} MB", size as f64 / (1024 * 1024) as f64)
-    } else if size / 1024 != 0 {
-        format!("{:.2} KB", size as f64 / (1024) as f64)
-    } else {
-        format!("{:.2} Bytes", size)
+/// Prints the roots of `args` like GNU du would, then exits with the status
+/// du would: 1 when any size is incomplete
+async fn du(args: DuArgs) -> ! {
+    let scanner = match args.scanner() {
+        Ok(scanner) => scanner,
+        Err(err) => {
+            eprintln!("ERROR: {err}");
+            std::process::exit(2);
+        }
+    };
+    let mut roots = vec![];
+    for root in &args.paths {
+        roots.push(scanner.scan(root).await.root);
+    }
+
+    let roots = weights::count_once(roots);
+
+    let mut out = BufWriter::new(stdout().lock());
+    let roots = roots.iter().collect::<Vec<_>>();
+    if let Err(err) = weights::write_du(&mut out, &roots, &args.options()).and_then(|_| out.flush())
+    {
+        eprintln!("ERROR: Writing output: {err}");
+        std::process::exit(1);
     }
-}
 
-fn format_path(path: &Path) -> String {
-    let out = path.to_str().unwrap_or("<UNKNOWN>");
-    let len = out.len();
-    if len > 50 {
-        format!("{}...{}", &out[0..20], &out[len - 20..])
-    } else {
-        out.to_owned()
+    let mut err = stderr().lock();
+    for root in &roots {
+        let _ = weights::write_errors(&mut err, root, false);
     }
+    std::process::exit(i32::from(roots.iter().any(|root| root.is_incomplete())))
 }
 
-impl FSEntity {
-    async fn file(name: impl Into<PathBuf>) -> Self {
-        let path = name.into();
-        FSEntity {
-            size: metadata(&path).await.map(|map| map.len()).unwrap_or(0),
-            path,
-            kind: FSType::File,
+/// Scans `path` and reprints its report after every `interval` in which
+/// inotify reported changes, or after a full rescan every `interval` when the
+/// folders cannot be watched. Runs until the process is interrupted
+async fn watch(path: &Path, scanner: Scanner, options: &PrintOptions, interval: Duration) -> ! {
+    let mut root = scanner.scan(path).await.root;
+    let mut watch = start_watch(&root, interval);
+    let mut changed = true;
+
+    loop {
+        if changed {
+            let mut out = BufWriter::new(stdout().lock());
+            if stdout().is_terminal() {
+                let _ = write!(out, "\x1b[2J\x1b[H");
+            }
+            let result = weights::write_text(&mut out, &root, options).and_then(|_| out.flush());
+            if let Err(err) = result {
+                eprintln!("ERROR: Writing output: {err}");
+                std::process::exit(1);
+            }
         }
-    }
 
-    async fn folder(name: impl Into<PathBuf>) -> Self {
-        let mut entity = FSEntity {
-            path: name.into(),
-            size: 0,
-            kind: FSType::Folder(vec![]),
+        let Some(mut current) = watch.take() else {
+            sleep(interval).await;
+            root = scanner.scan(path).await.root;
+            changed = true;
+            continue;
         };
-        entity.size = entity.calculate_size().await;
-        entity
-    }
+        let (current, changes) = spawn_blocking(move || {
+            let changes = current.wait(interval);
+            (current, changes)
+        })
+        .await;
 
-    fn calculate_size(&mut self) -> BoxFuture<u64> {
-        async move {
-            let mut tasks = vec![];
+        let paths = match changes {
+            Ok(Changes::Paths(paths)) => paths,
+            Ok(Changes::Overflow) => {
+                root = scanner.scan(path).await.root;
+                watch = start_watch(&root, interval);
+                changed = true;
+                continue;
+            }
+            Err(err) => {
+                eprintln!("ERROR: Watching {}: {err}", path.display());
+                std::process::exit(1);
+            }
+        };
+        watch = Some(current);
+        changed = !paths.is_empty();
 
-            let Ok(mut dir) = read_dir(self.path.to_string_lossy().into_owned()).await else {
-                return 0;
+        for path in &paths {
+            scanner.refresh(&mut root, path).await;
+            let added = match (&mut watch, root.find(path)) {
+                (Some(watch), Some(entity)) => watch.add(entity),
+                _ => Ok(()),
             };
-
-            let list = self.kind.list_mut();
-
-            while let Some(entry) = dir.next().await {
-                let Ok(entry) = entry else {
-                    eprintln!("ERROR: Getting next entry");
-                    continue;
-                };
-                let path = entry.path();
-                let Ok(file_type) = entry.file_type().await else {
-                    eprintln!("ERROR: Getting file type");
-                    continue;
-                };
-
-                if file_type.is_file() {
-                    list.push(FSEntity::file(path).await)
-                } else {
-                    tasks.push(spawn(async { FSEntity::folder(path).await }));
-                }
+            if let Err(err) = added {
+                warn_unwatched(path, &err, interval);
+                watch = None;
             }
-            let mut results = join_all(tasks).await;
-            list.append(&mut results);
-            list.sort_by(|a, b| b.cmp(a));
-            self.size += list.iter().map(|x| x.size).sum::<u64>();
-            self.size
         }
-        .boxed()
     }
 }
 
-fn print(parent: &FSEntity, level: u32) {
-    let mut prefix = (0..level).map(|_| "|").collect::<String>();
-    prefix.push_str("|_");
-
-    let list = parent.kind.list();
+fn start_watch(root: &FSEntity, interval: Duration) -> Option<Watch> {
+    Watch::new(root)
+        .map_err(|err| warn_unwatched(root.path(), &err, interval))
+        .ok()
+}
 
-    for entity in list.iter() {
-        let path = &entity.path;
-        let ratio = if entity.size != 0 {
-            entity.size as f64 * 100.0 / parent.size as f64
-        } else {
-            0.0
-        };
+fn warn_unwatched(path: &Path, err: &std::io::Error, interval: Duration) {
+    eprintln!(
+        "WARNING: Cannot watch {}: {err}, rescanning every {}s instead",
+        path.display(),
+        interval.as_secs()
+    );
+}
 
-        println!(
-            "{typ}\t[{size} = {ratio:.2}%]\t{prefix} {path}",
-            typ = entity.kind,
-            path = format_path(path),
-            size = format_size(entity.size),
-        );
+fn save(file: &Path, root: &FSEntity) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(file)?);
+    weights::write_snapshot(&mut out, root)?;
+    out.flush()
+}
 
-        if let FSType::Folder(_) = entity.kind {
-            print(entity, level + 1)
+/// Applies `action` to `paths` once the user confirms, or only prints what it
+/// would free for a dry run. The plan and the results go to stderr next to
+/// the prompt, so the report on stdout stays parseable. Returns `false` if
+/// anything failed
+async fn apply(
+    action: Action,
+    paths: &[PathBuf],
+    scans: &mut [Scan],
+    dry_run: bool,
+    format: &SizeFormat,
+) -> bool {
+    let mut plans = vec![];
+    for scan in scans.iter() {
+        let selected = paths
+            .iter()
+            .filter_map(|path| scan.root.locate(path))
+            .filter(|path| scan.root.find(path).is_some())
+            .collect::<Vec<_>>();
+        match Plan::new(&scan.root, action, &selected) {
+            Ok(plan) => plans.push(plan),
+            Err(path) => {
+                eprintln!("ERROR: {} cannot be selected", path.display());
+                return false;
+            }
         }
     }
-}
 
-#[async_std::main]
-async fn main() {
-    println!("{}", std::env::current_dir().unwrap().display());
+    let planned = plans.iter().map(|plan| plan.entries.len()).sum::<usize>();
+    if planned != paths.len() {
+        eprintln!("ERROR: Some paths are not part of the scanned roots");
+        return false;
+    }
 
-    let f = FSEntity::folder(".".to_owned()).await;
-    print(&f, 0);
+    let mut out = stderr().lock();
+    for plan in &plans {
+        let _ = weights::write_plan(&mut out, plan, format);
+    }
+    if dry_run {
+        return true;
+    }
+
+    eprint!("{action} {planned} entries? [y/N] ");
+    let mut answer = String::new();
+    if stdin().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y") {
+        eprintln!("Cancelled");
+        return true;
+    }
+
+    let mut ok = true;
+    for (plan, scan) in plans.into_iter().zip(scans.iter_mut()) {
+        for applied in plan.execute(&mut scan.root).await {
+            match applied.result {
+                Ok(size) => {
+                    let done = match action {
+                        Action::Delete => "Deleted",
+                        Action::Trash => "Trashed",
+                    };
+                    let _ = writeln!(
+                        out,
+                        "{done}\t[{}]\t{}",
+                        format.format(size),
+                        applied.path.display()
+                    );
+                }
+                Err(err) => {
+                    eprintln!("ERROR: {action} {}: {err}", applied.path.display());
+                    ok = false;
+                }
+            }
+        }
+    }
+    ok
 }
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::fs::{canonicalize, read_to_string};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A mount point met during a scan
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    /// Path of the mount point as it was reached from the scan root
+    pub path: PathBuf,
+    /// File system type from `/proc/self/mountinfo`, when it could be found
+    pub fs_type: Option<String>,
+    /// Whether the scan stopped at this mount point
+    pub skipped: bool,
+}
+
+/// File system types of the mount table, keyed by mount point
+pub(crate) struct MountTable(HashMap<PathBuf, String>);
+
+impl MountTable {
+    pub fn load() -> Self {
+        Self::parse(&read_to_string("/proc/self/mountinfo").unwrap_or_default())
+    }
+
+    /// Parses the `mountinfo` format described in proc(5); later mounts
+    /// on the same path shadow earlier ones
+    fn parse(mountinfo: &str) -> Self {
+        let mut table = HashMap::new();
+        for line in mountinfo.lines() {
+            let mut fields = line.split(' ');
+            let Some(mount_point) = fields.nth(4) else {
+                continue;
+            };
+            let Some(fs_type) = fields.skip_while(|&field| field != "-").nth(1) else {
+                continue;
+            };
+            table.insert(PathBuf::from(unescape(mount_point)), fs_type.to_owned());
+        }
+        MountTable(table)
+    }
+
+    pub fn fs_type(&self, path: &Path) -> Option<String> {
+        let path = canonicalize(path).ok()?;
+        self.0.get(&path).cloned()
+    }
+}
+
+/// Decodes the octal escapes (`\040` for a space) used by the kernel
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (bytes[i], octal) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Writes every mount point met during a scan with its file system type
+pub fn write_mounts(out: &mut impl Write, mounts: &[Mount]) -> io::Result<()> {
+    for mount in mounts {
+        writeln!(
+            out,
+            "MOUNT\t{}\t{}{}",
+            mount.fs_type.as_deref().unwrap_or("unknown"),
+            mount.path.display(),
+            if mount.skipped { "\t(skipped)" } else { "" }
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::entity::FSEntity;
+use crate::format::{escape_path, SizeFormat};
+use crate::json::write_one_or_many;
+use crate::largest::own_bytes;
+use crate::report::ratio;
+
+/// Bytes and number of files of a part of a tree, such as those of one user
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub size: u64,
+    pub files: u64,
+}
+
+/// Usage of a tree per user and per group, largest first. Entries whose
+/// owner could not be read are counted under `None`
+#[derive(Clone, Debug, Default)]
+pub struct Ownership {
+    pub users: Vec<(Option<u32>, Usage)>,
+    pub groups: Vec<(Option<u32>, Usage)>,
+}
+
+/// Sums the bytes and files below `root` per owner of every entry, a folder
+/// adding its own blocks
+pub fn ownership(root: &FSEntity) -> Ownership {
+    let mut users: HashMap<Option<u32>, Usage> = HashMap::new();
+    let mut groups: HashMap<Option<u32>, Usage> = HashMap::new();
+
+    for (entity, own) in own_bytes(root) {
+        let owner = entity.owner();
+        for usage in [
+            users.entry(owner.map(|owner| owner.uid)).or_default(),
+            groups.entry(owner.map(|owner| owner.gid)).or_default(),
+        ] {
+            usage.size += own;
+            usage.files += u64::from(entity.is_file());
+        }
+    }
+
+    Ownership {
+        users: sorted(users),
+        groups: sorted(groups),
+    }
+}
+
+fn sorted(map: HashMap<Option<u32>, Usage>) -> Vec<(Option<u32>, Usage)> {
+    let mut list = map.into_iter().collect::<Vec<_>>();
+    list.sort_by(|(a_id, a), (b_id, b)| b.size.cmp(&a.size).then(a_id.cmp(b_id)));
+    list
+}
+
+/// User and group names of the local system
+#[derive(Clone, Debug, Default)]
+pub struct Names {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl Names {
+    /// Reads `/etc/passwd` and `/etc/group`; ids missing from them are
+    /// shown as numbers
+    pub fn load() -> Self {
+        Names {
+            users: parse_ids("/etc/passwd"),
+            groups: parse_ids("/etc/group"),
+        }
+    }
+
+    pub fn user(&self, uid: Option<u32>) -> String {
+        name(&self.users, uid)
+    }
+
+    pub fn group(&self, gid: Option<u32>) -> String {
+        name(&self.groups, gid)
+    }
+}
+
+fn name(names: &HashMap<u32, String>, id: Option<u32>) -> String {
+    match id {
+        Some(id) => names.get(&id).cloned().unwrap_or_else(|| id.to_string()),
+        None => "<unknown>".to_owned(),
+    }
+}
+
+/// Names by id from a file of `name:password:id:...` lines
+fn parse_ids(file: &str) -> HashMap<u32, String> {
+    read_to_string(file)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse().ok()?;
+            Some((id, name.to_owned()))
+        })
+        .collect()
+}
+
+/// Writes the usage per user and per group of `root`, then of every folder
+/// directly inside it
+pub fn write_owners(
+    out: &mut impl Write,
+    root: &FSEntity,
+    names: &Names,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    write_folder(out, root, names, format)?;
+    for folder in root.children().iter().filter(|child| child.is_folder()) {
+        write_folder(out, folder, names, format)?;
+    }
+    Ok(())
+}
+
+fn write_folder(
+    out: &mut impl Write,
+    folder: &FSEntity,
+    names: &Names,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    let ownership = ownership(folder);
+    writeln!(
+        out,
+        "{}\t[{}]",
+        folder.path.display(),
+        format.format(folder.size)
+    )?;
+    for (uid, usage) in &ownership.users {
+        write_line(out, "USER", &names.user(*uid), usage, folder.size, format)?;
+    }
+    for (gid, usage) in &ownership.groups {
+        write_line(out, "GROUP", &names.group(*gid), usage, folder.size, format)?;
+    }
+    Ok(())
+}
+
+fn write_line(
+    out: &mut impl Write,
+    typ: &str,
+    name: &str,
+    usage: &Usage,
+    total: u64,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{typ}\t[{size} = {ratio:.2}%]\t{files} files\t{name}",
+        size = format.format(usage.size),
+        ratio = ratio(usage.size, total),
+        files = usage.files,
+    )
+}
+
+#[derive(Serialize)]
+struct JsonOwnership {
+    path: String,
+    size: u64,
+    users: Vec<JsonUsage>,
+    groups: Vec<JsonUsage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    folders: Vec<JsonOwnership>,
+}
+
+#[derive(Serialize)]
+struct JsonUsage {
+    id: Option<u32>,
+    name: String,
+    size: u64,
+    files: u64,
+    percentage: f64,
+}
+
+impl JsonOwnership {
+    fn new(folder: &FSEntity, names: &Names, folders: Vec<JsonOwnership>) -> Self {
+        let ownership = ownership(folder);
+        let usages = |list: Vec<(Option<u32>, Usage)>, name: &dyn Fn(Option<u32>) -> String| {
+            list.into_iter()
+                .map(|(id, usage)| JsonUsage {
+                    id,
+                    name: name(id),
+                    size: usage.size,
+                    files: usage.files,
+                    percentage: ratio(usage.size, folder.size),
+                })
+                .collect()
+        };
+        JsonOwnership {
+            path: escape_path(&folder.path),
+            size: folder.size,
+            users: usages(ownership.users, &|uid| names.user(uid)),
+            groups: usages(ownership.groups, &|gid| names.group(gid)),
+            folders,
+        }
+    }
+}
+
+/// Writes the usage per owner of every root and of the folders directly
+/// inside it as a JSON object, or an array of them for several roots
+pub fn write_owners_json(
+    out: &mut impl Write,
+    roots: &[&FSEntity],
+    names: &Names,
+) -> io::Result<()> {
+    let reports = roots
+        .iter()
+        .map(|root| {
+            let folders = root
+                .children()
+                .iter()
+                .filter(|child| child.is_folder())
+                .map(|folder| JsonOwnership::new(folder, names, vec![]))
+                .collect();
+            JsonOwnership::new(root, names, folders)
+        })
+        .collect::<Vec<_>>();
+
+    write_one_or_many(out, &reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{FSType, Owner};
+    use crate::testing::TempDir;
+
+    fn owned(path: &str, size: u64, kind: FSType, uid: u32, gid: u32) -> FSEntity {
+        FSEntity {
+            owner: Some(Owner { uid, gid }),
+            ..FSEntity::fixture(path, size, kind)
+        }
+    }
+
+    #[test]
+    fn ownership_counts_own_bytes_per_user_and_group() {
+        let root = owned(
+            "t",
+            4,
+            FSType::Folder(vec![
+                owned("t/a", 10, FSType::File, 1, 100),
+                owned("t/b", 20, FSType::File, 2, 100),
+                owned("t/e", 0, FSType::File, 2, 100),
+                FSEntity::fixture("t/u", 3, FSType::File),
+            ]),
+            0,
+            0,
+        );
+        let ownership = ownership(&root);
+
+        let usage = |size, files| Usage { size, files };
+        assert_eq!(
+            ownership.users,
+            [
+                (Some(2), usage(20, 2)),
+                (Some(1), usage(10, 1)),
+                (Some(0), usage(4, 0)),
+                (None, usage(3, 1)),
+            ]
+        );
+        assert_eq!(
+            ownership.groups,
+            [
+                (Some(100), usage(30, 3)),
+                (Some(0), usage(4, 0)),
+                (None, usage(3, 1))
+            ]
+        );
+    }
+
+    #[test]
+    fn names_come_from_id_files() {
+        let dir = TempDir::new();
+        let passwd = dir.file(
+            "passwd",
+            b"# comment\nroot:x:0:0:root:/root:/bin/sh\nbroken\nalice:x:1000:1000::/home/alice:/bin/sh\n",
+        );
+        let names = Names {
+            users: parse_ids(passwd.to_str().unwrap()),
+            groups: HashMap::new(),
+        };
+
+        assert_eq!(names.user(Some(1000)), "alice");
+        assert_eq!(names.user(Some(0)), "root");
+        assert_eq!(names.user(Some(7)), "7");
+        assert_eq!(names.user(None), "<unknown>");
+        assert_eq!(names.group(Some(0)), "0");
+    }
+}
@@ -0,0 +1,278 @@
+use std::cmp::Reverse;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::age::unix_time;
+use crate::boxes::write_boxes;
+use crate::entity::{FSEntity, TimeField};
+use crate::format::{escape_path, format_path, SizeFormat, Threshold};
+use crate::scanner::ScanStats;
+
+/// How [`write_text`] draws the tree
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TreeStyle {
+    /// One tab separated line per entry with its full path behind `|_`
+    /// markers, easy to parse
+    #[default]
+    Plain,
+    /// Box drawing branches, right aligned sizes and a bar of the share of
+    /// every entry, colored with ANSI escapes when `color` is set
+    Boxes { color: bool },
+}
+
+/// Filters applied when a scanned tree is printed or exported
+#[derive(Clone, Debug, Default)]
+pub struct PrintOptions {
+    /// Do not descend more than this many levels below the root
+    pub max_depth: Option<u32>,
+    /// Only keep the N largest entries of every folder
+    pub top: Option<usize>,
+    /// Hide entries smaller than this many bytes
+    pub min_size: u64,
+    /// Hide entries with anything below them newer than this
+    pub older_than: Option<SystemTime>,
+    /// Time the age of the entries is measured by
+    pub time: TimeField,
+    /// Shorten longer paths to this many columns; they are printed whole
+    /// when it is not set
+    pub path_width: Option<usize>,
+    /// Drawing of the text tree
+    pub style: TreeStyle,
+    /// Merge the entries of a folder below this into one line of the text
+    /// tree
+    pub collapse: Option<Threshold>,
+    /// Units and precision sizes are written with
+    pub size_format: SizeFormat,
+}
+
+/// Entries of a folder merged into one line of the text tree
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Collapsed {
+    pub entries: usize,
+    pub size: u64,
+    pub apparent_size: u64,
+    pub disk_size: u64,
+}
+
+impl Collapsed {
+    pub(crate) fn name(&self, options: &PrintOptions) -> String {
+        format!(
+            "<{} other entries: {}>",
+            self.entries,
+            options.size(self.size)
+        )
+    }
+}
+
+impl PrintOptions {
+    /// `size` bytes written in the size format
+    pub(crate) fn size(&self, size: u64) -> String {
+        self.size_format.format(size)
+    }
+
+    pub(crate) fn descends(&self, level: u32) -> bool {
+        self.max_depth.is_none_or(|max| level < max)
+    }
+
+    /// `path` escaped, and shortened to the path width
+    pub(crate) fn path(&self, path: &Path) -> String {
+        match self.path_width {
+            Some(width) => format_path(path, width),
+            None => escape_path(path),
+        }
+    }
+
+    pub(crate) fn visible<'a>(&self, list: &'a [FSEntity]) -> Vec<&'a FSEntity> {
+        let mut visible = list
+            .iter()
+            .filter(|entity| entity.size >= self.min_size)
+            .filter(|entity| {
+                self.older_than.is_none_or(|cutoff| {
+                    entity
+                        .newest(self.time)
+                        .is_some_and(|newest| newest < unix_time(cutoff))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(top) = self.top.filter(|&top| visible.len() > top) {
+            let mut by_size = visible.clone();
+            by_size.sort_by_key(|entity| Reverse(entity.size));
+            by_size.truncate(top);
+            visible.retain(|entity| by_size.iter().any(|kept| std::ptr::eq(*kept, *entity)));
+        }
+        visible
+    }
+
+    /// The visible children of `parent` to print, and those merged by the
+    /// collapse threshold. A single entry below it is kept as it is
+    pub(crate) fn rows<'a>(&self, parent: &'a FSEntity) -> (Vec<&'a FSEntity>, Option<Collapsed>) {
+        let visible = self.visible(parent.children());
+        let Some(threshold) = self.collapse else {
+            return (visible, None);
+        };
+
+        let (small, kept): (Vec<&FSEntity>, Vec<&FSEntity>) = visible
+            .iter()
+            .partition(|entity| threshold.below(entity.size, parent.size));
+        if small.len() < 2 {
+            return (visible, None);
+        }
+        let collapsed = small
+            .iter()
+            .fold(Collapsed::default(), |sum, entity| Collapsed {
+                entries: sum.entries + 1,
+                size: sum.size + entity.size,
+                apparent_size: sum.apparent_size + entity.apparent_size,
+                disk_size: sum.disk_size + entity.disk_size,
+            });
+        (kept, Some(collapsed))
+    }
+}
+
+pub(crate) fn ratio(size: u64, parent_size: u64) -> f64 {
+    if size != 0 && parent_size != 0 {
+        size as f64 * 100.0 / parent_size as f64
+    } else {
+        0.0
+    }
+}
+
+/// Apparent and disk size side by side, which makes sparse files stand out
+fn both_sizes(entity: &FSEntity, options: &PrintOptions) -> String {
+    format!(
+        "apparent {}, disk {}",
+        options.size(entity.apparent_size),
+        options.size(entity.disk_size)
+    )
+}
+
+/// Writes the human readable tree of `root`, headed by its total size
+pub fn write_text(out: &mut impl Write, root: &FSEntity, options: &PrintOptions) -> io::Result<()> {
+    if let TreeStyle::Boxes { color } = options.style {
+        return write_boxes(out, root, options, color);
+    }
+    writeln!(
+        out,
+        "{}\t[{}]\t[{}]",
+        root.path.display(),
+        options.size(root.size),
+        both_sizes(root, options)
+    )?;
+    print(out, root, 0, options)
+}
+
+fn print(
+    out: &mut impl Write,
+    parent: &FSEntity,
+    level: u32,
+    options: &PrintOptions,
+) -> io::Result<()> {
+    if !options.descends(level) {
+        return Ok(());
+    }
+
+    let mut prefix = (0..level).map(|_| "|").collect::<String>();
+    prefix.push_str("|_");
+
+    let (rows, collapsed) = options.rows(parent);
+    for entity in rows {
+        writeln!(
+            out,
+            "{typ}\t[{size} = {ratio:.2}%]\t[{sizes}]\t{prefix} {path}",
+            typ = entity.kind,
+            path = options.path(&entity.path),
+            size = options.size(entity.size),
+            sizes = both_sizes(entity, options),
+            ratio = ratio(entity.size, parent.size),
+        )?;
+
+        if entity.is_folder() {
+            print(out, entity, level + 1, options)?
+        }
+    }
+
+    if let Some(collapsed) = collapsed {
+        writeln!(
+            out,
+            "OTHER\t[{size} = {ratio:.2}%]\t[apparent {apparent}, disk {disk}]\t{prefix} {name}",
+            size = options.size(collapsed.size),
+            ratio = ratio(collapsed.size, parent.size),
+            apparent = options.size(collapsed.apparent_size),
+            disk = options.size(collapsed.disk_size),
+            name = collapsed.name(options),
+        )?;
+    }
+
+    if parent.excluded_size != 0 {
+        writeln!(
+            out,
+            "EXCLUDED\t[{size}]\t[not counted]\t{prefix} <excluded>",
+            size = options.size(parent.excluded_size),
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the totals gathered during a scan that are not visible in the tree
+pub fn write_summary(
+    out: &mut impl Write,
+    stats: &ScanStats,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    if stats.hard_links != 0 {
+        writeln!(
+            out,
+            "Hard links: {} names deduplicated, {} saved",
+            stats.hard_links,
+            format.format(stats.hard_link_savings)
+        )?;
+    }
+    if stats.cached_dirs != 0 {
+        writeln!(out, "Cache: {} folders reused", stats.cached_dirs)?;
+    }
+    for path in &stats.stale_dirs {
+        writeln!(out, "STALE\t{}", path.display())?;
+    }
+    if !stats.stale_dirs.is_empty() {
+        writeln!(
+            out,
+            "Cache: {} folders changed without a new modification time",
+            stats.stale_dirs.len()
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the failures met below `root` unless `quiet`, followed by a count
+/// per kind of failure. Nothing is written for a complete scan
+pub fn write_errors(out: &mut impl Write, root: &FSEntity, quiet: bool) -> io::Result<()> {
+    let mut total = 0;
+    let mut by_kind: Vec<(io::ErrorKind, u64)> = vec![];
+    for error in root.all_errors() {
+        if !quiet {
+            writeln!(out, "ERROR: {error}")?;
+        }
+        total += 1;
+        match by_kind.iter_mut().find(|(kind, _)| *kind == error.io_kind) {
+            Some((_, count)) => *count += 1,
+            None => by_kind.push((error.io_kind, 1)),
+        }
+    }
+
+    if total != 0 {
+        by_kind.sort_by_key(|&(_, count)| Reverse(count));
+        let kinds = by_kind
+            .iter()
+            .map(|(kind, count)| format!("{kind}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            out,
+            "{}: {total} errors ({kinds}), sizes are incomplete",
+            root.path.display()
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,591 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_lock::{Semaphore, SemaphoreGuard};
+use async_std::fs::{metadata, read_dir, symlink_metadata, ReadDir};
+use async_std::task::sleep;
+
+use globset::Glob;
+
+use crate::cache::{DirCache, Stat};
+use crate::entity::FSEntity;
+use crate::filter::{Filter, Ignores};
+use crate::mounts::{Mount, MountTable};
+use crate::snapshot::{is_snapshot, read_snapshot};
+
+/// Order of the entries inside every scanned folder
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortBy {
+    /// Folders first, then largest entries first
+    #[default]
+    Size,
+    /// Alphabetical by path
+    Name,
+}
+
+/// Which size of an entry is counted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SizeMode {
+    /// Length of the contents, as `ls -l` or `du --apparent-size` report it
+    #[default]
+    Apparent,
+    /// Blocks allocated on disk, as `du` and `df` report it
+    Disk,
+}
+
+impl SizeMode {
+    pub(crate) fn pick(self, apparent: u64, disk: u64) -> u64 {
+        match self {
+            SizeMode::Apparent => apparent,
+            SizeMode::Disk => disk,
+        }
+    }
+}
+
+/// How files with several hard links are counted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HardLinks {
+    /// Count every inode once, on the first path it is seen through
+    #[default]
+    First,
+    /// Give every name an equal share of the size of the inode
+    Split,
+    /// Count the full size for every name
+    All,
+}
+
+/// Totals gathered while scanning, next to the tree itself
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Names of hard linked files whose size was not counted in full
+    pub hard_links: u64,
+    /// Bytes not counted thanks to hard link deduplication
+    pub hard_link_savings: u64,
+    /// Mount points met below the root, whether descended into or not
+    pub mounts: Vec<Mount>,
+    /// Folders whose entries were taken from the cache instead of the disk
+    pub cached_dirs: u64,
+    /// Folders whose cached entries no longer match the disk, found while
+    /// validating the cache
+    pub stale_dirs: Vec<PathBuf>,
+}
+
+/// Result of [`Scanner::scan`]
+#[derive(Debug)]
+pub struct Scan {
+    pub root: FSEntity,
+    pub stats: ScanStats,
+}
+
+/// Builder for a directory scan
+///
+/// ```no_run
+/// # async_std::task::block_on(async {
+/// let tree = weights::Scanner::new()
+///     .sort(weights::SortBy::Name)
+///     .scan("/var/log")
+///     .await
+///     .root;
+/// println!("{}", weights::format_size(tree.size()));
+/// # });
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Scanner {
+    pub(crate) sort: SortBy,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) hard_links: HardLinks,
+    pub(crate) size_mode: SizeMode,
+    pub(crate) one_file_system: bool,
+    pub(crate) exclude: Vec<Glob>,
+    pub(crate) include: Vec<Glob>,
+    pub(crate) gitignore: bool,
+    pub(crate) report_excluded: bool,
+    pub(crate) jobs: Option<usize>,
+    pub(crate) cache: Option<PathBuf>,
+    pub(crate) validate_cache: bool,
+}
+
+impl Scanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Order of the children of every folder in the resulting tree
+    pub fn sort(mut self, sort: SortBy) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Descend into symlinked folders and size symlinked files by their
+    /// target; every folder is still visited once, which breaks link cycles
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// How files reachable through several hard links are counted
+    pub fn hard_links(mut self, hard_links: HardLinks) -> Self {
+        self.hard_links = hard_links;
+        self
+    }
+
+    /// Which size is used for sorting, totals and [`FSEntity::size`]; both
+    /// sizes are recorded on every entity regardless
+    pub fn size_mode(mut self, size_mode: SizeMode) -> Self {
+        self.size_mode = size_mode;
+        self
+    }
+
+    /// Stop at mount points instead of descending into other file systems
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Leaves out every entry whose name or path relative to the root
+    /// matches the glob `pattern`
+    pub fn exclude(mut self, pattern: &str) -> Result<Self, globset::Error> {
+        self.exclude.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Only counts files whose name or relative path matches one of the
+    /// included glob patterns; folders are always descended into
+    pub fn include(mut self, pattern: &str) -> Result<Self, globset::Error> {
+        self.include.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Honor `.gitignore` and `.ignore` files of every folder and its parents
+    pub fn gitignore(mut self, gitignore: bool) -> Self {
+        self.gitignore = gitignore;
+        self
+    }
+
+    /// Measure the excluded entries into [`FSEntity::excluded_size`] of
+    /// their folder, which means scanning excluded folders too
+    pub fn report_excluded(mut self, report_excluded: bool) -> Self {
+        self.report_excluded = report_excluded;
+        self
+    }
+
+    /// Maximum number of folders read at the same time, which bounds the
+    /// file descriptors a scan uses. Defaults to [`default_jobs`]
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = Some(jobs.max(1));
+        self
+    }
+
+    /// Folder keeping an index of the folders of every scanned root. A
+    /// folder whose inode and modification time did not change since the
+    /// previous scan is not listed again: the names of its entries come from
+    /// the index, while their metadata is still read from the disk
+    pub fn cache(mut self, dir: Option<PathBuf>) -> Self {
+        self.cache = dir;
+        self
+    }
+
+    /// Read every folder even when it is cached, and report the cached
+    /// folders whose entries differ from the disk in [`ScanStats::stale_dirs`].
+    /// The index is refreshed either way
+    pub fn validate_cache(mut self, validate: bool) -> Self {
+        self.validate_cache = validate;
+        self
+    }
+
+    fn context(&self, root: &Path, cache: Option<DirCache>) -> Arc<ScanContext> {
+        let open_dirs = Arc::new(Semaphore::new(self.jobs.unwrap_or_else(default_jobs)));
+        let excluded = self
+            .report_excluded
+            .then(|| Arc::new(self.context_with(root, None, open_dirs.clone(), None)));
+        Arc::new(self.context_with(root, cache, open_dirs, excluded))
+    }
+
+    fn context_with(
+        &self,
+        root: &Path,
+        cache: Option<DirCache>,
+        open_dirs: Arc<Semaphore>,
+        excluded: Option<Arc<ScanContext>>,
+    ) -> ScanContext {
+        ScanContext {
+            config: self.clone(),
+            filter: Filter::new(root, &self.exclude, &self.include, self.gitignore),
+            open_dirs,
+            visited: Mutex::default(),
+            inodes: Mutex::default(),
+            hard_links: AtomicU64::default(),
+            hard_link_savings: AtomicU64::default(),
+            mounts: Mutex::default(),
+            cache,
+            excluded,
+        }
+    }
+
+    /// Scans `root` and everything below it
+    pub async fn scan(&self, root: impl Into<PathBuf>) -> Scan {
+        let root = root.into();
+        let cache = self
+            .cache
+            .as_ref()
+            .map(|dir| DirCache::load(dir, &root, self.validate_cache));
+        let context = self.context(&root, cache);
+        if self.follow_symlinks {
+            // A root whose metadata cannot be read records the failure below
+            let _ = context.first_visit(&root).await;
+        }
+        // A root that is not a folder is measured on its own, like du does
+        let root = match metadata(&root).await {
+            Ok(meta) if meta.is_file() => FSEntity::file(root, &mut None, &context).await,
+            Ok(meta) if !meta.is_dir() => {
+                let kind = meta.file_type().into();
+                FSEntity::other(root, kind, &mut None, &context).await
+            }
+            Ok(_) => FSEntity::folder(root, None, Ignores::default(), context.clone()).await,
+            Err(err) => FSEntity::unreadable(root, err, &context),
+        };
+
+        let mut mounts = std::mem::take(&mut *context.mounts.lock().unwrap());
+        if !mounts.is_empty() {
+            let table = MountTable::load();
+            for mount in mounts.iter_mut() {
+                mount.fs_type = table.fs_type(&mount.path);
+            }
+            mounts.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+
+        let (cached_dirs, stale_dirs) = match &context.cache {
+            Some(cache) => {
+                // The index only saves time; a scan is still complete without it
+                let _ = cache.save();
+                (cache.reused(), cache.take_stale())
+            }
+            None => (0, vec![]),
+        };
+
+        Scan {
+            root,
+            stats: ScanStats {
+                hard_links: context.hard_links.load(Ordering::Relaxed),
+                hard_link_savings: context.hard_link_savings.load(Ordering::Relaxed),
+                mounts,
+                cached_dirs,
+                stale_dirs,
+            },
+        }
+    }
+
+    /// Reads `path` as a snapshot file, or scans it when it is anything else
+    pub async fn load(&self, path: impl Into<PathBuf>) -> io::Result<FSEntity> {
+        let path = path.into();
+        if path.is_file() {
+            let mut input = BufReader::new(File::open(&path)?);
+            if is_snapshot(&mut input).unwrap_or(false) {
+                return read_snapshot(&mut input);
+            }
+        }
+        Ok(self.scan(path).await.root)
+    }
+
+    /// Measures `path` again and puts the result in the scanned tree `root`,
+    /// removing it when it is gone or now excluded. Nothing happens if the
+    /// folder holding `path` is not in the tree. The cache is not used, and
+    /// hard links are only deduplicated within `path`
+    pub async fn refresh(&self, root: &mut FSEntity, path: &Path) {
+        if path == root.path() {
+            *root = self.clone().cache(None).scan(path).await.root;
+            return;
+        }
+        let Some(parent) = path.parent().filter(|parent| root.find(parent).is_some()) else {
+            return;
+        };
+
+        let context = self.context(root.path(), None);
+        let mut ignores = Ignores::default();
+        for dir in parent.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            if dir.starts_with(root.path()) {
+                ignores = context.filter.enter(dir, &ignores);
+            }
+        }
+
+        let Ok(meta) = symlink_metadata(path).await else {
+            root.remove(path);
+            return;
+        };
+        if context.filter.excludes(path, meta.is_dir(), &ignores) {
+            root.remove(path);
+            return;
+        }
+
+        let target = match meta.is_symlink() && self.follow_symlinks {
+            true => metadata(path).await.ok(),
+            false => None,
+        };
+        let entity = if meta.is_dir() || target.as_ref().is_some_and(|target| target.is_dir()) {
+            let dev = metadata(parent).await.ok().map(|meta| meta.dev());
+            FSEntity::folder(path, dev, ignores, context.clone()).await
+        } else if meta.is_file() || target.is_some_and(|target| target.is_file()) {
+            FSEntity::file(path, &mut None, &context).await
+        } else if meta.is_symlink() {
+            FSEntity::symlink(path, &mut None, &context).await
+        } else {
+            FSEntity::other(path, meta.file_type().into(), &mut None, &context).await
+        };
+        root.insert(entity);
+        root.sort_along(path, self.sort);
+    }
+}
+
+/// Number of folders read at the same time when [`Scanner::jobs`] is not
+/// set: half of the soft `RLIMIT_NOFILE`, leaving room for everything else
+pub fn default_jobs() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes into the struct it is given
+    let soft = match unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } {
+        0 => limit.rlim_cur,
+        _ => 1024,
+    };
+    (soft / 2).clamp(1, 1024) as usize
+}
+
+/// Attempts made to open a folder while the process is out of descriptors
+const EMFILE_RETRIES: u32 = 8;
+
+/// State shared by all the tasks of a single scan
+pub(crate) struct ScanContext {
+    pub config: Scanner,
+    pub filter: Filter,
+    open_dirs: Arc<Semaphore>,
+    visited: Mutex<HashSet<(u64, u64)>>,
+    inodes: Mutex<HashSet<(u64, u64)>>,
+    hard_links: AtomicU64,
+    hard_link_savings: AtomicU64,
+    mounts: Mutex<Vec<Mount>>,
+    pub cache: Option<DirCache>,
+    /// Where excluded folders are measured, with inodes, mounts and hard
+    /// links of their own so that they change no count of the scan
+    excluded: Option<Arc<ScanContext>>,
+}
+
+impl ScanContext {
+    /// Records the folder at `path` by (device, inode), returning `false` if
+    /// it was already visited during this scan
+    pub async fn first_visit(&self, path: &Path) -> io::Result<bool> {
+        let meta = metadata(path).await?;
+        Ok(self
+            .visited
+            .lock()
+            .unwrap()
+            .insert((meta.dev(), meta.ino())))
+    }
+
+    /// Opens a folder once one of the `jobs` slots is free, retrying with a
+    /// backoff when the process or the system runs out of file descriptors
+    pub async fn open_dir(&self, path: &Path) -> io::Result<(ReadDir, SemaphoreGuard<'_>)> {
+        let permit = self.open_dirs.acquire().await;
+        let mut delay = Duration::from_millis(10);
+        let mut attempt = 0;
+        loop {
+            match read_dir(path).await {
+                Ok(dir) => return Ok((dir, permit)),
+                Err(err)
+                    if attempt < EMFILE_RETRIES
+                        && matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) =>
+                {
+                    attempt += 1;
+                    sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Context to measure an excluded folder in; within one, itself
+    pub fn excluded(self: &Arc<Self>) -> Arc<ScanContext> {
+        self.excluded.clone().unwrap_or_else(|| self.clone())
+    }
+
+    pub fn crossed_mount(&self, path: &Path, skipped: bool) {
+        self.mounts.lock().unwrap().push(Mount {
+            path: path.to_owned(),
+            fs_type: None,
+            skipped,
+        });
+    }
+
+    /// Apparent and disk size a file counts for, taking hard links to the
+    /// same inode into account; `None` for a name of an inode already
+    /// counted under another one
+    pub fn file_sizes(&self, stat: &Stat) -> Option<(u64, u64)> {
+        let sizes = stat.sizes();
+        if stat.nlink < 2 {
+            return Some(sizes);
+        }
+
+        let counted = match self.config.hard_links {
+            HardLinks::All => return Some(sizes),
+            HardLinks::Split => Some((sizes.0 / stat.nlink, sizes.1 / stat.nlink)),
+            HardLinks::First => {
+                let first = self.inodes.lock().unwrap().insert((stat.dev, stat.ino));
+                if first {
+                    return Some(sizes);
+                }
+                None
+            }
+        };
+
+        let mode = self.config.size_mode;
+        let (apparent, disk) = counted.unwrap_or_default();
+        self.hard_links.fetch_add(1, Ordering::Relaxed);
+        self.hard_link_savings.fetch_add(
+            mode.pick(sizes.0, sizes.1) - mode.pick(apparent, disk),
+            Ordering::Relaxed,
+        );
+        counted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    use async_std::task::block_on;
+
+    use super::*;
+    use crate::entity::{FSType, OtherKind};
+    use crate::error::ScanOp;
+    use crate::testing::TempDir;
+
+    fn kind_of<'a>(root: &'a FSEntity, path: &Path) -> &'a FSType {
+        &root.find(path).unwrap().kind
+    }
+
+    #[test]
+    fn links_and_special_files_are_not_descended() {
+        let dir = TempDir::new();
+        let file = dir.file("sub/f", &[0; 100]);
+        symlink(dir.path().join("sub"), dir.path().join("link")).unwrap();
+        let fifo = CString::new(dir.path().join("fifo").as_os_str().as_bytes()).unwrap();
+        // SAFETY: the path is a valid C string
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let root = block_on(Scanner::new().scan(dir.path())).root;
+        assert!(matches!(
+            kind_of(&root, &dir.path().join("link")),
+            FSType::Symlink
+        ));
+        assert!(matches!(
+            kind_of(&root, &dir.path().join("fifo")),
+            FSType::Other(OtherKind::Fifo)
+        ));
+        assert!(root.errors.is_empty());
+
+        let root = block_on(Scanner::new().scan(&file)).root;
+        assert!(matches!(root.kind, FSType::File));
+        assert_eq!(root.size, 100);
+    }
+
+    #[test]
+    fn followed_links_are_measured_once() {
+        let dir = TempDir::new();
+        dir.file("sub/f", &[0; 100]);
+        symlink(dir.path().join("sub"), dir.path().join("link")).unwrap();
+        symlink(dir.path(), dir.path().join("sub/loop")).unwrap();
+
+        let root = block_on(Scanner::new().follow_symlinks(true).scan(dir.path())).root;
+        // Whichever name comes first is measured, with the file and the
+        // link back to the root, which is not followed again
+        let children =
+            ["sub", "link"].map(|name| root.find(&dir.path().join(name)).unwrap().children().len());
+        assert!(children == [2, 0] || children == [0, 2], "{children:?}");
+        assert_eq!(root.count(), 4);
+        assert!(root.errors.is_empty());
+    }
+
+    #[test]
+    fn hard_links_are_counted_as_asked() {
+        let dir = TempDir::new();
+        let file = dir.file("a", &[0; 100]);
+        std::fs::hard_link(&file, dir.path().join("b")).unwrap();
+        let own = std::fs::metadata(dir.path()).unwrap().len();
+
+        let scan = block_on(Scanner::new().scan(dir.path()));
+        assert_eq!(scan.root.apparent_size, own + 100);
+        assert_eq!(
+            (scan.stats.hard_links, scan.stats.hard_link_savings),
+            (1, 100)
+        );
+
+        let scanner = Scanner::new().hard_links(HardLinks::Split);
+        let scan = block_on(scanner.scan(dir.path()));
+        assert_eq!(scan.root.apparent_size, own + 100);
+        assert!(scan.root.children().iter().all(|child| child.size == 50));
+        assert_eq!(
+            (scan.stats.hard_links, scan.stats.hard_link_savings),
+            (2, 100)
+        );
+
+        let scanner = Scanner::new().hard_links(HardLinks::All);
+        let scan = block_on(scanner.scan(dir.path()));
+        assert_eq!(scan.root.apparent_size, own + 200);
+        assert_eq!(
+            (scan.stats.hard_links, scan.stats.hard_link_savings),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn reporting_excluded_bytes_changes_no_total() {
+        let dir = TempDir::new();
+        let file = dir.file("skipped/a", &[0; 100]);
+        std::fs::hard_link(&file, dir.path().join("kept")).unwrap();
+        dir.file("skipped/b", &[0; 50]);
+
+        let scanner = Scanner::new().exclude("skipped").unwrap();
+        let plain = block_on(scanner.scan(dir.path()));
+        let reported = block_on(scanner.report_excluded(true).scan(dir.path()));
+
+        assert_eq!(plain.root.size, reported.root.size);
+        assert_eq!(plain.root.apparent_size, reported.root.apparent_size);
+        assert_eq!(plain.stats, reported.stats);
+        let own = std::fs::metadata(dir.path().join("skipped")).unwrap().len();
+        assert_eq!(reported.root.excluded_size, own + 150);
+    }
+
+    #[test]
+    fn unreadable_root_records_one_error() {
+        let dir = TempDir::new();
+        let missing = dir.path().join("missing");
+
+        let root = block_on(Scanner::new().scan(&missing)).root;
+        assert_eq!(root.errors.len(), 1);
+        assert_eq!(root.errors[0].op, ScanOp::Metadata);
+        assert_eq!(root.size, 0);
+    }
+
+    #[test]
+    fn unlisted_folder_keeps_its_own_size() {
+        // Listing a file fails like listing a folder without permission,
+        // which does not fail for root
+        let dir = TempDir::new();
+        let file = dir.file("f", &[0; 100]);
+        let context = Scanner::new().context(&file, None);
+
+        let folder = block_on(FSEntity::folder(&file, None, Ignores::default(), context));
+        assert_eq!(folder.errors.len(), 1);
+        assert_eq!(folder.errors[0].op, ScanOp::ReadDir);
+        assert_eq!((folder.size, folder.apparent_size), (100, 100));
+    }
+}
@@ -0,0 +1,283 @@
+use std::ffi::OsString;
+use std::io::{self, BufRead, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+
+use crate::entity::{FSEntity, FSType, OtherKind, Owner, Times};
+
+/// First bytes of every snapshot file
+const MAGIC: &[u8; 4] = b"WGHT";
+const VERSION: u8 = 3;
+
+/// Oldest version still read; it only lacks the owners and the times
+const OLDEST_VERSION: u8 = 1;
+
+/// Set on the kind of an entry followed by its owner
+const HAS_OWNER: u8 = 0x80;
+
+/// Set on the kind of an entry followed by its modification and access times
+const HAS_TIMES: u8 = 0x40;
+
+/// Writes `root` as a compact binary snapshot: the tree in pre-order, every
+/// entry with its name relative to its parent and its sizes as varints
+pub fn write_snapshot(out: &mut impl Write, root: &FSEntity) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    write_bytes(out, root.path.as_os_str().as_bytes())?;
+    write_entity(out, root)
+}
+
+/// Reads a tree written by [`write_snapshot`]
+pub fn read_snapshot(input: &mut impl BufRead) -> io::Result<FSEntity> {
+    let mut header = [0; 5];
+    input.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a snapshot file"));
+    }
+    if !(OLDEST_VERSION..=VERSION).contains(&header[4]) {
+        return Err(invalid("unsupported snapshot version"));
+    }
+    let path = PathBuf::from(OsString::from_vec(read_bytes(input)?));
+    read_entity(input, path)
+}
+
+/// Whether `input` starts like a snapshot, without consuming it
+pub fn is_snapshot(input: &mut impl BufRead) -> io::Result<bool> {
+    Ok(input.fill_buf()?.starts_with(MAGIC))
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn kind_tag(kind: &FSType) -> u8 {
+    match kind {
+        FSType::Folder(_) => 0,
+        FSType::File => 1,
+        FSType::MountPoint => 2,
+        FSType::Symlink => 3,
+        FSType::Other(OtherKind::Socket) => 4,
+        FSType::Other(OtherKind::Fifo) => 5,
+        FSType::Other(OtherKind::BlockDevice) => 6,
+        FSType::Other(OtherKind::CharDevice) => 7,
+        FSType::Other(OtherKind::Unknown) => 8,
+    }
+}
+
+fn write_entity(out: &mut impl Write, entity: &FSEntity) -> io::Result<()> {
+    let mut tag = kind_tag(&entity.kind);
+    if entity.owner.is_some() {
+        tag |= HAS_OWNER;
+    }
+    if entity.times.is_some() {
+        tag |= HAS_TIMES;
+    }
+    out.write_all(&[tag])?;
+    if let Some(owner) = entity.owner {
+        write_varint(out, owner.uid.into())?;
+        write_varint(out, owner.gid.into())?;
+    }
+    if let Some(times) = entity.times {
+        write_varint(out, times.modified as u64)?;
+        write_varint(out, times.accessed as u64)?;
+    }
+    write_varint(out, entity.size)?;
+    write_varint(out, entity.apparent_size)?;
+    write_varint(out, entity.disk_size)?;
+    write_varint(out, entity.excluded_size)?;
+
+    if let FSType::Folder(children) = &entity.kind {
+        write_varint(out, children.len() as u64)?;
+        for child in children {
+            let name = child.path.file_name().unwrap_or_default();
+            write_bytes(out, name.as_bytes())?;
+            write_entity(out, child)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_entity(input: &mut impl Read, path: PathBuf) -> io::Result<FSEntity> {
+    let mut tag = [0];
+    input.read_exact(&mut tag)?;
+    let owner = match tag[0] & HAS_OWNER != 0 {
+        true => Some(Owner {
+            uid: read_varint(input)? as u32,
+            gid: read_varint(input)? as u32,
+        }),
+        false => None,
+    };
+    let times = match tag[0] & HAS_TIMES != 0 {
+        true => Some(Times {
+            modified: read_varint(input)? as i64,
+            accessed: read_varint(input)? as i64,
+        }),
+        false => None,
+    };
+    let size = read_varint(input)?;
+    let apparent_size = read_varint(input)?;
+    let disk_size = read_varint(input)?;
+    let excluded_size = read_varint(input)?;
+
+    let kind = match tag[0] & !(HAS_OWNER | HAS_TIMES) {
+        0 => {
+            let count = read_varint(input)?;
+            let mut children = Vec::with_capacity(count.min(1 << 16) as usize);
+            for _ in 0..count {
+                let name = OsString::from_vec(read_bytes(input)?);
+                children.push(read_entity(input, path.join(name))?);
+            }
+            FSType::Folder(children)
+        }
+        1 => FSType::File,
+        2 => FSType::MountPoint,
+        3 => FSType::Symlink,
+        4 => FSType::Other(OtherKind::Socket),
+        5 => FSType::Other(OtherKind::Fifo),
+        6 => FSType::Other(OtherKind::BlockDevice),
+        7 => FSType::Other(OtherKind::CharDevice),
+        8 => FSType::Other(OtherKind::Unknown),
+        _ => return Err(invalid("unknown entry kind")),
+    };
+
+    Ok(FSEntity {
+        path,
+        size,
+        apparent_size,
+        disk_size,
+        excluded_size,
+        kind,
+        owner,
+        times,
+        linked: false,
+        errors: vec![],
+    })
+}
+
+pub(crate) fn write_varint(out: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub(crate) fn read_varint(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+pub(crate) fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_varint(out, bytes.len() as u64)?;
+    out.write_all(bytes)
+}
+
+pub(crate) fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_varint(input)?;
+    let mut bytes = vec![0; len.min(1 << 16) as usize];
+    if bytes.len() as u64 != len {
+        return Err(invalid("name too long"));
+    }
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn varints_round_trip() {
+        let values = [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u32::MAX as u64,
+            u64::MAX,
+        ];
+        let mut out = vec![];
+        for value in values {
+            write_varint(&mut out, value).unwrap();
+        }
+        assert_eq!(out[..3], [0, 1, 127]);
+        assert_eq!(out[3..5], [0x80, 0x01]);
+
+        let mut input = Cursor::new(out);
+        for value in values {
+            assert_eq!(read_varint(&mut input).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn read_varint_rejects_overlong_and_truncated_input() {
+        assert!(read_varint(&mut Cursor::new([0xff; 11])).is_err());
+        assert!(read_varint(&mut Cursor::new([0x80])).is_err());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut out = vec![];
+        write_bytes(&mut out, b"").unwrap();
+        write_bytes(&mut out, b"\xffname").unwrap();
+
+        let mut input = Cursor::new(out);
+        assert_eq!(read_bytes(&mut input).unwrap(), b"");
+        assert_eq!(read_bytes(&mut input).unwrap(), b"\xffname");
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut big = FSEntity::fixture("root/big", 1 << 40, FSType::File);
+        big.disk_size = 4096;
+        big.owner = Some(Owner {
+            uid: 1000,
+            gid: u32::MAX,
+        });
+        big.times = Some(Times {
+            modified: 1_700_000_000,
+            accessed: -1,
+        });
+        let mut sub = FSEntity::fixture("root/sub", 10, FSType::Folder(vec![]));
+        sub.excluded_size = 42;
+        let root = FSEntity::fixture(
+            "root",
+            4096,
+            FSType::Folder(vec![
+                big,
+                sub,
+                FSEntity::fixture("root/link", 4, FSType::Symlink),
+                FSEntity::fixture("root/mnt", 0, FSType::MountPoint),
+                FSEntity::fixture("root/sock", 0, FSType::Other(OtherKind::Socket)),
+            ]),
+        );
+
+        let mut out = vec![];
+        write_snapshot(&mut out, &root).unwrap();
+        assert!(is_snapshot(&mut Cursor::new(&out)).unwrap());
+        assert_eq!(read_snapshot(&mut Cursor::new(out)).unwrap(), root);
+    }
+
+    #[test]
+    fn read_snapshot_rejects_other_files() {
+        assert!(read_snapshot(&mut Cursor::new(b"WGHI\x03")).is_err());
+        assert!(read_snapshot(&mut Cursor::new(b"WGHT\x09")).is_err());
+        assert!(read_snapshot(&mut Cursor::new(b"WGHT\x03\x04root")).is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use std::cmp::Reverse;
+use std::io::{self, stdout, Write};
+use std::path::{Path, PathBuf};
+
+use async_std::task::spawn_blocking;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use weights::{Action, FSEntity, Plan, Scanner, SizeFormat, SizeMode};
+
+const BAR_WIDTH: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Order {
+    Size,
+    Name,
+    Count,
+}
+
+/// Interactive browser over a scanned tree
+struct Browser {
+    root: FSEntity,
+    scanner: Scanner,
+    /// Path of the folder being listed
+    current: PathBuf,
+    selected: usize,
+    offset: usize,
+    order: Order,
+    size_mode: SizeMode,
+    size_format: SizeFormat,
+    status: String,
+    dry_run: bool,
+    /// Action waiting for the user to confirm it
+    pending: Option<Plan>,
+}
+
+/// Restores the terminal even when drawing fails half way
+struct RawScreen;
+
+impl RawScreen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, cursor::Hide)?;
+        Ok(RawScreen)
+    }
+}
+
+impl Drop for RawScreen {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), LeaveAlternateScreen, cursor::Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Browses `root` until the user quits; `scanner` is used to rescan subtrees.
+/// With `dry_run` the delete and trash actions only tell what they would free
+pub async fn run(
+    root: FSEntity,
+    scanner: Scanner,
+    size_mode: SizeMode,
+    size_format: SizeFormat,
+    dry_run: bool,
+) -> io::Result<FSEntity> {
+    let mut browser = Browser {
+        current: root.path().to_owned(),
+        root,
+        scanner,
+        selected: 0,
+        offset: 0,
+        order: Order::Size,
+        size_mode,
+        size_format,
+        status: String::new(),
+        dry_run,
+        pending: None,
+    };
+
+    let _screen = RawScreen::enter()?;
+    loop {
+        browser.draw()?;
+        let event = spawn_blocking(event::read).await?;
+        let Event::Key(key) = event else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if !browser.handle(key).await {
+            break;
+        }
+    }
+    Ok(browser.root)
+}
+
+impl Browser {
+    fn folder(&self) -> &FSEntity {
+        self.root.find(&self.current).unwrap_or(&self.root)
+    }
+
+    /// Children of the current folder in display order
+    fn rows(&self) -> Vec<&FSEntity> {
+        let mut rows = self.folder().children().iter().collect::<Vec<_>>();
+        match self.order {
+            Order::Size => rows.sort_by_key(|entity| Reverse(entity.size_in(self.size_mode))),
+            Order::Name => rows.sort_by(|a, b| a.path().cmp(b.path())),
+            Order::Count => rows.sort_by_cached_key(|entity| Reverse(entity.count())),
+        }
+        rows
+    }
+
+    fn selected_path(&self) -> Option<PathBuf> {
+        self.rows()
+            .get(self.selected)
+            .map(|entity| entity.path().to_owned())
+    }
+
+    /// Applies a key press, returning `false` once the user quits
+    async fn handle(&mut self, key: KeyEvent) -> bool {
+        let len = self.folder().children().len();
+        let page = terminal::size()
+            .map(|(_, h)| h as usize)
+            .unwrap_or(24)
+            .saturating_sub(4);
+        self.status.clear();
+
+        if let Some(plan) = self.pending.take() {
+            if key.code == KeyCode::Char('y') {
+                self.execute(plan).await;
+            } else {
+                self.status = "Cancelled".to_owned();
+            }
+            return true;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(len.saturating_sub(1))
+            }
+            KeyCode::PageUp => self.selected = self.selected.saturating_sub(page),
+            KeyCode::PageDown => self.selected = (self.selected + page).min(len.saturating_sub(1)),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = len.saturating_sub(1),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.enter(),
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => self.leave(),
+            KeyCode::Char('s') => self.order = Order::Size,
+            KeyCode::Char('n') => self.order = Order::Name,
+            KeyCode::Char('c') => self.order = Order::Count,
+            KeyCode::Char('a') => {
+                self.size_mode = match self.size_mode {
+                    SizeMode::Apparent => SizeMode::Disk,
+                    SizeMode::Disk => SizeMode::Apparent,
+                }
+            }
+            KeyCode::Char('r') => self.rescan().await,
+            KeyCode::Char('d') => self.plan(Action::Delete),
+            KeyCode::Char('t') => self.plan(Action::Trash),
+            _ => {}
+        }
+        true
+    }
+
+    fn enter(&mut self) {
+        let Some(path) = self.selected_path() else {
+            return;
+        };
+        if self.root.find(&path).is_some_and(FSEntity::is_folder) {
+            self.current = path;
+            self.selected = 0;
+            self.offset = 0;
+        }
+    }
+
+    fn leave(&mut self) {
+        if self.current == self.root.path() {
+            return;
+        }
+        let Some(parent) = self.current.parent().map(Path::to_owned) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut self.current, parent);
+        self.selected = self
+            .rows()
+            .iter()
+            .position(|entity| entity.path() == previous)
+            .unwrap_or(0);
+    }
+
+    /// Asks for confirmation of `action` on the selected entry
+    fn plan(&mut self, action: Action) {
+        let Some(path) = self.selected_path() else {
+            return;
+        };
+        let Ok(plan) = Plan::new(&self.root, action, std::slice::from_ref(&path)) else {
+            return;
+        };
+        let freed = self.size_format.format(plan.freed());
+        if self.dry_run {
+            self.status = format!("{action} {} would free {freed}", path.display());
+        } else {
+            self.status = format!("{action} {} and free {freed}? [y/N]", path.display());
+            self.pending = Some(plan);
+        }
+    }
+
+    async fn execute(&mut self, plan: Plan) {
+        for applied in plan.execute(&mut self.root).await {
+            self.status = match applied.result {
+                Ok(size) => format!(
+                    "Freed {} from {}",
+                    self.size_format.format(size),
+                    applied.path.display()
+                ),
+                Err(err) => format!("ERROR: {}: {err}", applied.path.display()),
+            };
+        }
+    }
+
+    /// Rescans the selected folder, or the current one when a file is selected
+    async fn rescan(&mut self) {
+        let path = match self.selected_path() {
+            Some(path) if self.root.find(&path).is_some_and(FSEntity::is_folder) => path,
+            _ => self.current.clone(),
+        };
+        self.status = format!("Scanning {}...", path.display());
+        let _ = self.draw();
+
+        self.scanner.refresh(&mut self.root, &path).await;
+        self.status = format!("Rescanned {}", path.display());
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let visible = height.saturating_sub(3).max(1);
+
+        let len = self.folder().children().len();
+        self.selected = self.selected.min(len.saturating_sub(1));
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + visible {
+            self.offset = self.selected + 1 - visible;
+        }
+
+        let mut out = stdout().lock();
+        queue!(out, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+
+        let folder = self.folder();
+        let total = folder.size_in(self.size_mode);
+        let header = format!(
+            "{} [{} {}, {} entries]",
+            weights::escape_path(folder.path()),
+            self.size_format.format(total),
+            match self.size_mode {
+                SizeMode::Apparent => "apparent",
+                SizeMode::Disk => "disk",
+            },
+            folder.count(),
+        );
+        queue!(
+            out,
+            SetAttribute(Attribute::Reverse),
+            Print(fit(&header, width)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        for (row, entity) in self
+            .rows()
+            .into_iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(visible)
+        {
+            let size = entity.size_in(self.size_mode);
+            let ratio = if total != 0 {
+                size as f64 / total as f64
+            } else {
+                0.0
+            };
+            let filled = ((ratio * BAR_WIDTH as f64).round() as usize).min(BAR_WIDTH);
+            let name = entity
+                .path()
+                .file_name()
+                .map(|name| weights::escape_path(Path::new(name)))
+                .unwrap_or_default();
+            let line = format!(
+                "{:>12} {:>6.2}% [{}{}] {}{}",
+                self.size_format.format(size),
+                ratio * 100.0,
+                "#".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                name,
+                if entity.is_folder() { "/" } else { "" },
+            );
+
+            queue!(out, cursor::MoveTo(0, (row - self.offset + 1) as u16))?;
+            if row == self.selected {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(fit(&line, width)),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(fit(&line, width)))?;
+            }
+        }
+
+        let footer = match self.status.is_empty() {
+            true => {
+                "q:quit  enter/←:open/back  s/n/c:sort size/name/count  a:apparent/disk  r:rescan  d/t:delete/trash"
+            }
+            false => &self.status,
+        };
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            Print(fit(footer, width))
+        )?;
+        out.flush()
+    }
+}
+
+/// Cuts `line` to the terminal width, counting wide characters for two
+/// columns and never splitting a grapheme cluster
+fn fit(line: &str, width: usize) -> String {
+    let mut left = width;
+    let mut out = String::new();
+    for grapheme in line.graphemes(true) {
+        let Some(rest) = left.checked_sub(grapheme.width()) else {
+            break;
+        };
+        left = rest;
+        out.push_str(grapheme);
+    }
+    out
+}
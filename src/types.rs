@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::entity::FSEntity;
+use crate::format::{escape_path, SizeFormat};
+use crate::json::write_one_or_many;
+use crate::report::ratio;
+
+/// Category of the extensions no category claims
+const OTHER: &str = "other";
+
+/// Name shown for files without an extension
+const NO_EXTENSION: &str = "<none>";
+
+/// Built in categories and the extensions they group
+const DEFAULT_CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "media",
+        &[
+            "jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp", "heic", "svg", "ico", "raw",
+            "mp4", "mkv", "avi", "mov", "webm", "wmv", "flv", "m4v", "mp3", "flac", "wav", "ogg",
+            "opus", "aac", "m4a",
+        ],
+    ),
+    (
+        "archives",
+        &[
+            "zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "lz4", "7z", "rar", "iso", "deb", "rpm",
+            "jar", "whl",
+        ],
+    ),
+    (
+        "code",
+        &[
+            "rs", "c", "h", "cc", "cpp", "hpp", "go", "py", "js", "mjs", "ts", "tsx", "jsx",
+            "java", "kt", "rb", "php", "cs", "swift", "sh", "bash", "zsh", "pl", "lua", "sql",
+            "html", "css", "scss",
+        ],
+    ),
+    (
+        "documents",
+        &[
+            "pdf", "doc", "docx", "odt", "xls", "xlsx", "ods", "ppt", "pptx", "odp", "txt", "md",
+            "rst", "tex", "epub",
+        ],
+    ),
+    (
+        "data",
+        &[
+            "parquet", "csv", "tsv", "json", "ndjson", "jsonl", "xml", "yaml", "yml", "toml",
+            "avro", "orc", "db", "sqlite", "arrow", "feather", "h5", "npy",
+        ],
+    ),
+    ("logs", &["log", "out", "err", "journal"]),
+    (
+        "binaries",
+        &[
+            "o", "a", "so", "dylib", "dll", "exe", "rlib", "class", "pyc", "wasm", "bin", "elf",
+        ],
+    ),
+];
+
+/// Maps lower case extensions to the category they are counted in
+#[derive(Clone, Debug)]
+pub struct Categories(HashMap<String, String>);
+
+impl Default for Categories {
+    /// Media, archives, code, documents, data, logs and binaries
+    fn default() -> Self {
+        let mut categories = Categories(HashMap::new());
+        for (name, extensions) in DEFAULT_CATEGORIES {
+            categories.set(name, extensions.iter().copied());
+        }
+        categories
+    }
+}
+
+impl Categories {
+    /// Counts `extensions` in the category `name`, taking them away from
+    /// the category they were in
+    pub fn set<'a>(&mut self, name: &str, extensions: impl IntoIterator<Item = &'a str>) {
+        for extension in extensions {
+            let extension = extension.trim().trim_start_matches('.').to_lowercase();
+            if !extension.is_empty() {
+                self.0.insert(extension, name.to_owned());
+            }
+        }
+    }
+
+    /// Parses `NAME=EXT,EXT,...` as given on the command line
+    pub fn parse(&mut self, definition: &str) -> Result<(), String> {
+        let (name, extensions) = definition
+            .split_once('=')
+            .filter(|(name, _)| !name.trim().is_empty())
+            .ok_or_else(|| format!("invalid category `{definition}`, expected NAME=EXT,EXT"))?;
+        self.set(name.trim(), extensions.split(','));
+        Ok(())
+    }
+
+    pub fn category(&self, extension: &str) -> &str {
+        self.0.get(extension).map_or(OTHER, String::as_str)
+    }
+}
+
+/// Bytes and number of files of one extension or category
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TypeStats {
+    pub size: u64,
+    pub files: u64,
+}
+
+/// Sizes of the files of a tree per extension and per category, largest first
+#[derive(Clone, Debug, Default)]
+pub struct Breakdown {
+    pub extensions: Vec<(String, TypeStats)>,
+    pub categories: Vec<(String, TypeStats)>,
+    /// Every byte counted in the breakdown
+    pub total: TypeStats,
+}
+
+/// Sums the files below `root` per lower case extension and per category.
+/// With `sniff`, files without an extension are opened and named after the
+/// format their first bytes reveal, when it is a well known one
+pub fn breakdown(root: &FSEntity, categories: &Categories, sniff: bool) -> Breakdown {
+    let mut extensions: HashMap<String, TypeStats> = HashMap::new();
+    let mut pending = vec![root];
+    while let Some(entity) = pending.pop() {
+        pending.extend(entity.children());
+        if !entity.is_file() {
+            continue;
+        }
+
+        let extension = match entity.path.extension() {
+            Some(extension) => extension.to_string_lossy().to_lowercase(),
+            None => sniff
+                .then(|| sniff_type(&entity.path))
+                .flatten()
+                .unwrap_or(NO_EXTENSION)
+                .to_owned(),
+        };
+        let stats = extensions.entry(extension).or_default();
+        stats.size += entity.size;
+        stats.files += 1;
+    }
+
+    let mut by_category: HashMap<String, TypeStats> = HashMap::new();
+    let mut total = TypeStats::default();
+    for (extension, stats) in &extensions {
+        let category = by_category
+            .entry(categories.category(extension).to_owned())
+            .or_default();
+        for sum in [category, &mut total] {
+            sum.size += stats.size;
+            sum.files += stats.files;
+        }
+    }
+
+    Breakdown {
+        extensions: sorted(extensions),
+        categories: sorted(by_category),
+        total,
+    }
+}
+
+fn sorted(map: HashMap<String, TypeStats>) -> Vec<(String, TypeStats)> {
+    let mut list = map.into_iter().collect::<Vec<_>>();
+    list.sort_by(|(a_name, a), (b_name, b)| b.size.cmp(&a.size).then(a_name.cmp(b_name)));
+    list
+}
+
+/// Signatures of well known formats and the extension they stand for
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x7fELF", "elf"),
+    (b"%PDF-", "pdf"),
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"PK\x03\x04", "zip"),
+    (b"\x1f\x8b", "gz"),
+    (b"BZh", "bz2"),
+    (b"\xfd7zXZ\x00", "xz"),
+    (b"\x28\xb5\x2f\xfd", "zst"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+    (b"Rar!\x1a\x07", "rar"),
+    (b"PAR1", "parquet"),
+    (b"SQLite format 3\x00", "sqlite"),
+    (b"OggS", "ogg"),
+    (b"fLaC", "flac"),
+    (b"ID3", "mp3"),
+    (b"#!", "sh"),
+];
+
+/// Extension of the format `path` starts like, if it is a well known one
+pub fn sniff_type(path: &Path) -> Option<&'static str> {
+    let mut head = [0; 16];
+    let mut file = File::open(path).ok()?;
+    let mut len = 0;
+    while len < head.len() {
+        match file.read(&mut head[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => len += read,
+        }
+    }
+    let head = &head[..len];
+
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some("mp4");
+    }
+    if head.starts_with(b"RIFF") && head.len() >= 12 {
+        return match &head[8..12] {
+            b"WAVE" => Some("wav"),
+            b"WEBP" => Some("webp"),
+            b"AVI " => Some("avi"),
+            _ => None,
+        };
+    }
+    MAGIC
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+        .map(|(_, extension)| *extension)
+}
+
+/// Writes the size and file count of every category, then of every extension
+pub fn write_breakdown(
+    out: &mut impl Write,
+    root: &FSEntity,
+    breakdown: &Breakdown,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}\t[{}]\t{} files",
+        root.path.display(),
+        format.format(breakdown.total.size),
+        breakdown.total.files
+    )?;
+    for (name, stats) in &breakdown.categories {
+        write_line(out, "CATEGORY", name, stats, &breakdown.total, format)?;
+    }
+    for (name, stats) in &breakdown.extensions {
+        let name = match name.as_str() {
+            NO_EXTENSION => NO_EXTENSION.to_owned(),
+            name => format!(".{name}"),
+        };
+        write_line(out, "EXTENSION", &name, stats, &breakdown.total, format)?;
+    }
+    Ok(())
+}
+
+fn write_line(
+    out: &mut impl Write,
+    typ: &str,
+    name: &str,
+    stats: &TypeStats,
+    total: &TypeStats,
+    format: &SizeFormat,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{typ}\t[{size} = {ratio:.2}%]\t{files} files\t{name}",
+        size = format.format(stats.size),
+        ratio = ratio(stats.size, total.size),
+        files = stats.files,
+    )
+}
+
+#[derive(Serialize)]
+struct JsonBreakdown<'a> {
+    path: String,
+    size: u64,
+    files: u64,
+    categories: Vec<JsonType<'a>>,
+    extensions: Vec<JsonType<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonType<'a> {
+    name: &'a str,
+    size: u64,
+    files: u64,
+    percentage: f64,
+}
+
+fn json_types<'a>(list: &'a [(String, TypeStats)], total: &TypeStats) -> Vec<JsonType<'a>> {
+    list.iter()
+        .map(|(name, stats)| JsonType {
+            name,
+            size: stats.size,
+            files: stats.files,
+            percentage: ratio(stats.size, total.size),
+        })
+        .collect()
+}
+
+/// Writes the breakdown of every root as a JSON object, or an array of them
+/// for several roots
+pub fn write_breakdown_json(
+    out: &mut impl Write,
+    roots: &[(&FSEntity, &Breakdown)],
+) -> io::Result<()> {
+    let reports = roots
+        .iter()
+        .map(|(root, breakdown)| JsonBreakdown {
+            path: escape_path(&root.path),
+            size: breakdown.total.size,
+            files: breakdown.total.files,
+            categories: json_types(&breakdown.categories, &breakdown.total),
+            extensions: json_types(&breakdown.extensions, &breakdown.total),
+        })
+        .collect::<Vec<_>>();
+
+    write_one_or_many(out, &reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::FSType;
+    use crate::testing::TempDir;
+
+    fn stats(list: &[(String, TypeStats)]) -> Vec<(&str, u64, u64)> {
+        list.iter()
+            .map(|(name, stats)| (name.as_str(), stats.size, stats.files))
+            .collect()
+    }
+
+    #[test]
+    fn categories_parse_and_override() {
+        let mut categories = Categories::default();
+        assert_eq!(categories.category("rs"), "code");
+        assert_eq!(categories.category("unheard"), OTHER);
+
+        categories.parse("notes= .MD, txt ,").unwrap();
+        assert_eq!(categories.category("md"), "notes");
+        assert_eq!(categories.category("txt"), "notes");
+        assert_eq!(categories.category("pdf"), "documents");
+
+        assert!(categories.parse("md").is_err());
+        assert!(categories.parse(" =md").is_err());
+    }
+
+    #[test]
+    fn breakdown_sums_files_per_extension_and_category() {
+        let root = FSEntity::fixture(
+            "t",
+            4,
+            FSType::Folder(vec![
+                FSEntity::fixture("t/a.RS", 10, FSType::File),
+                FSEntity::fixture("t/b.rs", 5, FSType::File),
+                FSEntity::fixture("t/c.log", 20, FSType::File),
+                FSEntity::fixture("t/README", 1, FSType::File),
+                FSEntity::fixture("t/l.rs", 3, FSType::Symlink),
+            ]),
+        );
+        let breakdown = breakdown(&root, &Categories::default(), false);
+
+        assert_eq!(
+            stats(&breakdown.extensions),
+            [("log", 20, 1), ("rs", 15, 2), (NO_EXTENSION, 1, 1)]
+        );
+        assert_eq!(
+            stats(&breakdown.categories),
+            [("logs", 20, 1), ("code", 15, 2), (OTHER, 1, 1)]
+        );
+        assert_eq!(breakdown.total, TypeStats { size: 36, files: 4 });
+    }
+
+    #[test]
+    fn sniff_type_reads_the_signature() {
+        let dir = TempDir::new();
+        let cases: [(&[u8], Option<&str>); 6] = [
+            (b"%PDF-1.7 ...", Some("pdf")),
+            (b"\x00\x00\x00\x20ftypisom", Some("mp4")),
+            (b"RIFF\x00\x00\x00\x00WEBPVP8 ", Some("webp")),
+            (b"RIFF\x00\x00\x00\x00????", None),
+            (b"#!/bin/sh\n", Some("sh")),
+            (b"", None),
+        ];
+        for (index, (contents, expected)) in cases.into_iter().enumerate() {
+            let path = dir.file(&index.to_string(), contents);
+            assert_eq!(sniff_type(&path), expected, "{contents:?}");
+        }
+        assert_eq!(sniff_type(&dir.path().join("missing")), None);
+
+        let root = FSEntity::fixture(dir.path().join("0").to_str().unwrap(), 12, FSType::File);
+        let sniffed = breakdown(&root, &Categories::default(), true);
+        assert_eq!(stats(&sniffed.categories), [("documents", 12, 1)]);
+    }
+}
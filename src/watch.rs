@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::entity::FSEntity;
+
+/// Events that change what a folder holds or how large its files are
+const MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_ONLYDIR;
+
+/// Header of every event read from an inotify descriptor
+const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// What changed below the watched folders while waiting
+#[derive(Debug, PartialEq, Eq)]
+pub enum Changes {
+    /// Entries created, written to, moved or deleted; never holds both a
+    /// folder and something below it
+    Paths(Vec<PathBuf>),
+    /// The kernel dropped events, so the whole tree has to be scanned again
+    Overflow,
+}
+
+/// Watches every folder of a scanned tree with inotify
+#[derive(Debug)]
+pub struct Watch {
+    fd: OwnedFd,
+    folders: HashMap<i32, PathBuf>,
+}
+
+impl Watch {
+    /// Watches `root` and every folder below it. Fails when inotify is not
+    /// available or the watch limit of the user is exceeded
+    pub fn new(root: &FSEntity) -> io::Result<Self> {
+        // SAFETY: inotify_init1 has no preconditions
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut watch = Watch {
+            // SAFETY: the descriptor was just opened and nothing else owns it
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            folders: HashMap::new(),
+        };
+        watch.add(root)?;
+        Ok(watch)
+    }
+
+    /// Watches `entity` and every folder below it, when it is a folder
+    pub fn add(&mut self, entity: &FSEntity) -> io::Result<()> {
+        if !entity.is_folder() {
+            return Ok(());
+        }
+        let path = CString::new(entity.path().as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        // SAFETY: the path is a valid C string and the descriptor is open
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+        match wd {
+            // Folders removed since the scan are not worth failing for
+            -1 => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::NotFound => {}
+                err => return Err(err),
+            },
+            wd => {
+                self.folders.insert(wd, entity.path().to_owned());
+            }
+        }
+        entity
+            .children()
+            .iter()
+            .try_for_each(|child| self.add(child))
+    }
+
+    /// Number of folders being watched
+    pub fn len(&self) -> usize {
+        self.folders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folders.is_empty()
+    }
+
+    /// Blocks for `timeout`, gathering every change reported meanwhile
+    pub fn wait(&mut self, timeout: Duration) -> io::Result<Changes> {
+        let deadline = Instant::now() + timeout;
+        let mut paths = vec![];
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let mut poll = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: poll only writes into the single pollfd it is given
+            let ready = unsafe { libc::poll(&mut poll, 1, left.as_millis() as libc::c_int) };
+            match ready {
+                -1 => match io::Error::last_os_error() {
+                    err if err.kind() == io::ErrorKind::Interrupted => continue,
+                    err => return Err(err),
+                },
+                0 => break,
+                _ => {}
+            }
+
+            // SAFETY: read writes at most `buffer.len()` bytes into the buffer
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+            if read < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if self.parse(&buffer[..read as usize], &mut paths) {
+                return Ok(Changes::Overflow);
+            }
+        }
+
+        paths.sort();
+        paths.dedup();
+        let mut topmost: Vec<PathBuf> = vec![];
+        for path in paths {
+            if !topmost.last().is_some_and(|above| path.starts_with(above)) {
+                topmost.push(path);
+            }
+        }
+        Ok(Changes::Paths(topmost))
+    }
+
+    /// Adds the paths named by the events in `buffer`, returning `true` if
+    /// the event queue overflowed
+    fn parse(&mut self, mut buffer: &[u8], paths: &mut Vec<PathBuf>) -> bool {
+        while buffer.len() >= HEADER {
+            // SAFETY: the kernel writes whole events, each starting with a header
+            let event = unsafe {
+                buffer
+                    .as_ptr()
+                    .cast::<libc::inotify_event>()
+                    .read_unaligned()
+            };
+            let end = (HEADER + event.len as usize).min(buffer.len());
+            let name = &buffer[HEADER..end];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            buffer = &buffer[end..];
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                return true;
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.folders.remove(&event.wd);
+                continue;
+            }
+            if let (Some(folder), false) = (self.folders.get(&event.wd), name.is_empty()) {
+                paths.push(folder.join(OsStr::from_bytes(name)));
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::Path;
+
+    use async_std::task::block_on;
+
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::Scanner;
+
+    /// An event as the kernel writes it, with its name padded like it does
+    fn event(wd: i32, mask: u32, name: &str) -> Vec<u8> {
+        let len = match name.is_empty() {
+            true => 0,
+            false => (name.len() + 1).next_multiple_of(16),
+        };
+        let mut bytes = vec![];
+        bytes.extend(wd.to_ne_bytes());
+        bytes.extend(mask.to_ne_bytes());
+        bytes.extend(0u32.to_ne_bytes());
+        bytes.extend((len as u32).to_ne_bytes());
+        bytes.extend(name.as_bytes());
+        bytes.resize(HEADER + len, 0);
+        bytes
+    }
+
+    fn watching(folders: &[(i32, &str)]) -> Watch {
+        Watch {
+            fd: File::open("/dev/null").unwrap().into(),
+            folders: folders
+                .iter()
+                .map(|&(wd, path)| (wd, PathBuf::from(path)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_names_entries_of_watched_folders() {
+        let mut watch = watching(&[(1, "/t"), (2, "/t/a")]);
+        let buffer = [
+            event(1, libc::IN_CREATE, "new"),
+            event(2, libc::IN_MODIFY, "longer than sixteen bytes"),
+            event(2, libc::IN_MODIFY, ""),
+            event(3, libc::IN_DELETE, "unknown"),
+        ]
+        .concat();
+
+        let mut paths = vec![];
+        assert!(!watch.parse(&buffer, &mut paths));
+        assert_eq!(
+            paths,
+            [
+                Path::new("/t/new"),
+                Path::new("/t/a/longer than sixteen bytes")
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_forgets_removed_folders_and_reports_overflows() {
+        let mut watch = watching(&[(1, "/t"), (2, "/t/a")]);
+        let mut paths = vec![];
+
+        assert!(!watch.parse(&event(2, libc::IN_IGNORED, ""), &mut paths));
+        assert_eq!(watch.len(), 1);
+
+        let buffer = [
+            event(-1, libc::IN_Q_OVERFLOW, ""),
+            event(1, libc::IN_CREATE, "a"),
+        ]
+        .concat();
+        assert!(watch.parse(&buffer, &mut paths));
+        assert!(paths.is_empty());
+    }
+
+    #[test]
+    fn wait_reports_every_changed_path_once() {
+        let dir = TempDir::new();
+        let root = block_on(Scanner::new().scan(dir.path())).root;
+        let mut watch = Watch::new(&root).unwrap();
+
+        dir.file("a/b", b"");
+        dir.file("c", b"");
+        dir.file("c", b"again");
+        let changes = watch.wait(Duration::from_millis(100)).unwrap();
+        assert_eq!(
+            changes,
+            Changes::Paths(vec![dir.path().join("a"), dir.path().join("c")])
+        );
+    }
+}